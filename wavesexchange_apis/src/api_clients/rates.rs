@@ -28,19 +28,19 @@ pub mod dto {
     use bigdecimal::BigDecimal;
     use serde::{Deserialize, Serialize};
 
-    #[derive(Deserialize, Clone)]
+    #[derive(Debug, Deserialize, Clone)]
     pub struct RatesResponse {
         pub data: Vec<Rate>,
     }
 
-    #[derive(Deserialize, Clone)]
+    #[derive(Debug, Deserialize, Clone)]
     pub struct Rate {
         pub pair: String,
         pub heuristics: Vec<String>,
         pub data: RateData,
     }
 
-    #[derive(Deserialize, Clone)]
+    #[derive(Debug, Deserialize, Clone)]
     pub struct RateData {
         pub rate: BigDecimal,
         pub heuristic: Option<BigDecimal>,
@@ -1,11 +1,14 @@
 use crate::{error, ApiResult, BaseApi};
 use futures::{future::BoxFuture, Future};
-use reqwest::{Client, ClientBuilder, Error as ReqError, RequestBuilder, Response, StatusCode};
+use reqwest::{
+    header::HeaderValue, Client, ClientBuilder, Error as ReqError, RequestBuilder, Response,
+    StatusCode,
+};
 use serde::de::DeserializeOwned;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::marker::PhantomData;
-use wavesexchange_log::debug;
+use wavesexchange_log::{current_span, debug};
 
 #[derive(Clone, Debug)]
 pub struct HttpClient<A: BaseApi> {
@@ -59,7 +62,16 @@ impl<A: BaseApi> HttpClient<A> {
         req_info: impl Into<String>,
     ) -> ApiResult<Response> {
         let req_info = req_info.into();
-        let request = req.build().unwrap();
+        let mut request = req.build().unwrap();
+        // continue the trace of the current request in the upstream service
+        if let Some(span) = current_span() {
+            if let Ok(traceparent) = HeaderValue::from_str(&span.to_traceparent()) {
+                request
+                    .headers_mut()
+                    .entry("traceparent")
+                    .or_insert(traceparent);
+            }
+        }
         let method = request.method().as_str();
         let url = request.url().as_str();
         let log_method_url = format!("{method} {url}");
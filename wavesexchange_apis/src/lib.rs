@@ -3,6 +3,8 @@ mod error;
 mod tests;
 
 pub mod api_clients;
+#[cfg(feature = "loaders")]
+pub mod loaders;
 pub mod models;
 
 pub use clients::{grpc::GrpcClient, http::HttpClient};
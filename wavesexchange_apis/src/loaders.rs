@@ -0,0 +1,241 @@
+//! Batched and cached loaders on top of the api clients, enabled with the `loaders` feature.
+//!
+//! ```ignore
+//! let assets = AssetDetailsLoader::new(HttpClient::from_base_url(node_url));
+//! let details = assets.load_many(asset_ids).await?;
+//! ```
+//!
+//! Keys of a batch are split into chunks, so that urls with lists of ids don't exceed
+//! length limits of upstreams. Chunks are requested concurrently.
+
+use crate::api_clients::{node, rates, state};
+use crate::{AssetsService, Error, HttpClient, Node, RatesService, StateService};
+use async_trait::async_trait;
+use futures::future::try_join_all;
+use serde_json::json;
+use std::collections::HashMap;
+use wavesexchange_loaders::{CachedLoader, TimedCache};
+
+/// Default lifespans of cached values, in seconds
+pub mod ttl {
+    pub const ASSET_DETAILS: u64 = 600;
+    pub const ASSET_QUANTITIES: u64 = 30;
+    pub const DATA_ENTRIES: u64 = 10;
+    pub const RATES: u64 = 30;
+}
+
+// ids are passed in the url, 100 of them (up to ~5KB) are accepted by all upstreams
+const URL_CHUNK_SIZE: usize = 100;
+const BODY_CHUNK_SIZE: usize = 500;
+
+/// Asset details from the node, `None` for unknown assets and `WAVES`.
+///
+/// Unknown assets aren't cached, they may be issued later.
+#[derive(Clone)]
+pub struct AssetDetailsLoader {
+    client: HttpClient<Node>,
+}
+
+impl AssetDetailsLoader {
+    pub fn new(client: HttpClient<Node>) -> Self {
+        AssetDetailsLoader { client }
+    }
+}
+
+#[async_trait]
+impl CachedLoader<String, Option<node::dto::AssetDetailItem>> for AssetDetailsLoader {
+    type Cache = TimedCache<String, Option<node::dto::AssetDetailItem>>;
+    type Error = Error;
+
+    async fn load_fn(
+        &mut self,
+        keys: &[String],
+    ) -> Result<Vec<Option<node::dto::AssetDetailItem>>, Self::Error> {
+        // the node has no details for WAVES, so it isn't requested
+        let ids = keys
+            .iter()
+            .filter(|id| *id != "WAVES")
+            .cloned()
+            .collect::<Vec<_>>();
+        let chunks = ids
+            .chunks(URL_CHUNK_SIZE)
+            .map(|chunk| self.client.assets_details(chunk.iter().cloned()));
+        let details = try_join_all(chunks)
+            .await?
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|detail| match detail {
+                node::dto::AssetDetail::Ok(item) => Some((item.asset_id.clone(), item)),
+                node::dto::AssetDetail::Err(_) => None,
+            })
+            .collect::<HashMap<_, _>>();
+        Ok(keys.iter().map(|id| details.get(id).cloned()).collect())
+    }
+
+    fn init_cache() -> Self::Cache {
+        TimedCache::with_lifespan(ttl::ASSET_DETAILS)
+    }
+
+    fn cache_strategy(_: &String, detail: &Option<node::dto::AssetDetailItem>) -> bool {
+        detail.is_some()
+    }
+}
+
+/// Current quantities of assets from the assets service, `None` for unknown assets
+#[derive(Clone)]
+pub struct AssetQuantitiesLoader {
+    client: HttpClient<AssetsService>,
+}
+
+impl AssetQuantitiesLoader {
+    pub fn new(client: HttpClient<AssetsService>) -> Self {
+        AssetQuantitiesLoader { client }
+    }
+}
+
+#[async_trait]
+impl CachedLoader<String, Option<i64>> for AssetQuantitiesLoader {
+    type Cache = TimedCache<String, Option<i64>>;
+    type Error = Error;
+
+    async fn load_fn(&mut self, keys: &[String]) -> Result<Vec<Option<i64>>, Self::Error> {
+        let chunks = keys
+            .chunks(URL_CHUNK_SIZE)
+            .map(|chunk| self.client.get(chunk.iter().cloned(), None));
+        let quantities = try_join_all(chunks)
+            .await?
+            .into_iter()
+            .flat_map(|resp| resp.data)
+            .map(|asset| (asset.data.id, asset.data.quantity))
+            .collect::<HashMap<_, _>>();
+        Ok(keys.iter().map(|id| quantities.get(id).copied()).collect())
+    }
+
+    fn init_cache() -> Self::Cache {
+        TimedCache::with_lifespan(ttl::ASSET_QUANTITIES)
+    }
+}
+
+/// Data entries by `(address, key)` from the state service, `None` if there is no such entry
+#[derive(Clone)]
+pub struct DataEntriesLoader {
+    client: HttpClient<StateService>,
+}
+
+impl DataEntriesLoader {
+    pub fn new(client: HttpClient<StateService>) -> Self {
+        DataEntriesLoader { client }
+    }
+}
+
+#[async_trait]
+impl CachedLoader<(String, String), Option<state::dto::DataEntry>> for DataEntriesLoader {
+    type Cache = TimedCache<(String, String), Option<state::dto::DataEntry>>;
+    type Error = Error;
+
+    async fn load_fn(
+        &mut self,
+        keys: &[(String, String)],
+    ) -> Result<Vec<Option<state::dto::DataEntry>>, Self::Error> {
+        let chunks = keys
+            .chunks(BODY_CHUNK_SIZE)
+            .map(|chunk| self.client.search(entries_query(chunk), None, None));
+        let entries = try_join_all(chunks)
+            .await?
+            .into_iter()
+            .flat_map(|list| list.items)
+            .map(|entry| ((entry.address.clone(), entry.key.clone()), entry))
+            .collect::<HashMap<_, _>>();
+        Ok(keys.iter().map(|k| entries.get(k).cloned()).collect())
+    }
+
+    fn init_cache() -> Self::Cache {
+        TimedCache::with_lifespan(ttl::DATA_ENTRIES)
+    }
+}
+
+fn entries_query(keys: &[(String, String)]) -> serde_json::Value {
+    json!({
+        "filter": {
+            "in": {
+                "properties": [{ "address": {} }, { "key": {} }],
+                "values": keys
+                    .iter()
+                    .map(|(address, key)| json!([address, key]))
+                    .collect::<Vec<_>>(),
+            }
+        }
+    })
+}
+
+/// Rates by `(amount asset, price asset)` pair from the rates service
+#[derive(Clone)]
+pub struct RatesLoader {
+    client: HttpClient<RatesService>,
+}
+
+impl RatesLoader {
+    pub fn new(client: HttpClient<RatesService>) -> Self {
+        RatesLoader { client }
+    }
+}
+
+#[async_trait]
+impl CachedLoader<(String, String), Option<rates::dto::RateData>> for RatesLoader {
+    type Cache = TimedCache<(String, String), Option<rates::dto::RateData>>;
+    type Error = Error;
+
+    async fn load_fn(
+        &mut self,
+        keys: &[(String, String)],
+    ) -> Result<Vec<Option<rates::dto::RateData>>, Self::Error> {
+        let chunks = keys
+            .chunks(BODY_CHUNK_SIZE)
+            .map(|chunk| self.client.rates(chunk.iter().cloned()));
+        let by_pair = try_join_all(chunks)
+            .await?
+            .into_iter()
+            .flat_map(|resp| resp.data)
+            .map(|rate| (rate.pair, rate.data))
+            .collect::<HashMap<_, _>>();
+        Ok(keys
+            .iter()
+            .map(|(amount, price)| by_pair.get(&format!("{}/{}", amount, price)).cloned())
+            .collect())
+    }
+
+    fn init_cache() -> Self::Cache {
+        TimedCache::with_lifespan(ttl::RATES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::blockchains::MAINNET;
+    use wavesexchange_loaders::Loader;
+
+    #[test]
+    fn test_entries_query() {
+        let keys = vec![
+            ("addr1".to_string(), "key1".to_string()),
+            ("addr2".to_string(), "key2".to_string()),
+        ];
+        assert_eq!(
+            entries_query(&keys)["filter"]["in"]["values"],
+            json!([["addr1", "key1"], ["addr2", "key2"]])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_asset_quantities_loader() {
+        let loader =
+            AssetQuantitiesLoader::new(HttpClient::from_base_url(MAINNET::assets_service_url));
+        let quantities = loader
+            .load_many_ordered(vec!["WAVES".to_string(), "unknown".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(quantities, vec![Some(10000000000000000), None]);
+    }
+}
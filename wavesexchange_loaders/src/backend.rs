@@ -0,0 +1,357 @@
+use crate::cacher::{CacheKey, CacheVal, SharedObj};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "redis-backend")]
+pub use self::redis_backend::RedisBackend;
+
+pub type SharedCache<K, V> = Arc<dyn CacheBackend<K, V>>;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum BackendError {
+    #[error("Cache backend connection error: {0}")]
+    Connection(String),
+    #[error("Cache backend serialization error: {0}")]
+    Serialization(String),
+}
+
+/// Async cache storage, shared between replicas of a service.
+///
+/// Used by `CachedLoader::shared_cache` as the second cache level:
+/// it is checked for keys missing in the in-process cache before calling `load_fn`.
+#[async_trait]
+pub trait CacheBackend<K, V>: SharedObj {
+    /// Values are returned in the same order as keys, `None` for absent ones
+    async fn get_many(&self, keys: &[K]) -> Result<Vec<Option<V>>, BackendError>;
+
+    async fn set_many(&self, entries: Vec<(K, V)>) -> Result<(), BackendError>;
+
+    async fn remove_many(&self, keys: &[K]) -> Result<(), BackendError>;
+}
+
+/// In-process `CacheBackend`, mostly useful for tests and local setups
+pub struct InMemoryBackend<K, V> {
+    ttl: Option<Duration>,
+    entries: Mutex<HashMap<K, (V, Option<Instant>)>>,
+}
+
+impl<K: CacheKey, V: CacheVal> InMemoryBackend<K, V> {
+    /// Entries expire after `ttl`, or never if it is `None`
+    pub fn new(ttl: Option<Duration>) -> Self {
+        InMemoryBackend {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl<K: CacheKey, V: CacheVal> CacheBackend<K, V> for InMemoryBackend<K, V> {
+    async fn get_many(&self, keys: &[K]) -> Result<Vec<Option<V>>, BackendError> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, (_, expires_at)| expires_at.filter(|at| *at <= now).is_none());
+        Ok(keys
+            .iter()
+            .map(|key| entries.get(key).map(|(value, _)| value.clone()))
+            .collect())
+    }
+
+    async fn set_many(&self, new_entries: Vec<(K, V)>) -> Result<(), BackendError> {
+        let expires_at = self.ttl.map(|ttl| Instant::now() + ttl);
+        let mut entries = self.entries.lock().unwrap();
+        for (key, value) in new_entries {
+            entries.insert(key, (value, expires_at));
+        }
+        Ok(())
+    }
+
+    async fn remove_many(&self, keys: &[K]) -> Result<(), BackendError> {
+        let mut entries = self.entries.lock().unwrap();
+        for key in keys {
+            entries.remove(key);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "redis-backend")]
+mod redis_backend {
+    use super::{BackendError, CacheBackend};
+    use crate::cacher::{CacheKey, CacheVal};
+    use cached::async_mutex::Mutex;
+    use redis::aio::MultiplexedConnection;
+    use redis::{Client, RedisError};
+    use serde::{de::DeserializeOwned, Serialize};
+    use std::marker::PhantomData;
+    use std::time::Duration;
+
+    /// `CacheBackend` for Redis or any server speaking its protocol.
+    ///
+    /// Keys are stored as `<namespace>:<key as json>`, values are serialized to json.
+    pub struct RedisBackend<K, V> {
+        client: Client,
+        namespace: String,
+        ttl: Option<Duration>,
+        conn: Mutex<Option<MultiplexedConnection>>,
+        _pd: PhantomData<fn(K) -> V>,
+    }
+
+    impl<K, V> RedisBackend<K, V> {
+        /// Connection is established lazily on the first request
+        /// and re-established after connection errors.
+        ///
+        /// Entries expire after `ttl`, or never if it is `None`
+        pub fn new(
+            url: &str,
+            namespace: impl Into<String>,
+            ttl: Option<Duration>,
+        ) -> Result<Self, BackendError> {
+            Ok(RedisBackend {
+                client: Client::open(url).map_err(connection_error)?,
+                namespace: namespace.into(),
+                ttl,
+                conn: Mutex::new(None),
+                _pd: PhantomData,
+            })
+        }
+
+        fn redis_key(&self, key: &impl Serialize) -> Result<String, BackendError> {
+            let key = serde_json::to_string(key)
+                .map_err(|e| BackendError::Serialization(e.to_string()))?;
+            Ok(format!("{}:{}", self.namespace, key))
+        }
+
+        async fn query<T: redis::FromRedisValue>(
+            &self,
+            pipe: &redis::Pipeline,
+        ) -> Result<T, BackendError> {
+            let mut conn_lock = self.conn.lock().await;
+            let mut conn = match &*conn_lock {
+                Some(conn) => conn.clone(),
+                None => {
+                    let conn = self
+                        .client
+                        .get_multiplexed_tokio_connection()
+                        .await
+                        .map_err(connection_error)?;
+                    *conn_lock = Some(conn.clone());
+                    conn
+                }
+            };
+            drop(conn_lock);
+            let result = pipe.query_async(&mut conn).await;
+            if let Err(e) = &result {
+                if e.is_connection_dropped() || e.is_io_error() {
+                    *self.conn.lock().await = None;
+                }
+            }
+            result.map_err(connection_error)
+        }
+    }
+
+    #[async_trait]
+    impl<K, V> CacheBackend<K, V> for RedisBackend<K, V>
+    where
+        K: CacheKey + Serialize,
+        V: CacheVal + Serialize + DeserializeOwned,
+    {
+        async fn get_many(&self, keys: &[K]) -> Result<Vec<Option<V>>, BackendError> {
+            if keys.is_empty() {
+                return Ok(vec![]);
+            }
+            let mut cmd = redis::cmd("MGET");
+            for key in keys {
+                cmd.arg(self.redis_key(key)?);
+            }
+            let mut pipe = redis::pipe();
+            pipe.add_command(cmd);
+            let (values,): (Vec<Option<Vec<u8>>>,) = self.query(&pipe).await?;
+            values
+                .into_iter()
+                .map(|value| {
+                    value
+                        .map(|v| serde_json::from_slice(&v))
+                        .transpose()
+                        .map_err(|e| BackendError::Serialization(e.to_string()))
+                })
+                .collect()
+        }
+
+        async fn set_many(&self, entries: Vec<(K, V)>) -> Result<(), BackendError> {
+            if entries.is_empty() {
+                return Ok(());
+            }
+            let mut pipe = redis::pipe();
+            for (key, value) in entries {
+                let value = serde_json::to_vec(&value)
+                    .map_err(|e| BackendError::Serialization(e.to_string()))?;
+                let cmd = pipe.cmd("SET").arg(self.redis_key(&key)?).arg(value);
+                if let Some(ttl) = self.ttl {
+                    cmd.arg("PX").arg(ttl.as_millis() as u64);
+                }
+                cmd.ignore();
+            }
+            self.query(&pipe).await
+        }
+
+        async fn remove_many(&self, keys: &[K]) -> Result<(), BackendError> {
+            if keys.is_empty() {
+                return Ok(());
+            }
+            let mut cmd = redis::cmd("DEL");
+            for key in keys {
+                cmd.arg(self.redis_key(key)?);
+            }
+            let mut pipe = redis::pipe();
+            pipe.add_command(cmd).ignore();
+            self.query(&pipe).await
+        }
+    }
+
+    fn connection_error(e: RedisError) -> BackendError {
+        BackendError::Connection(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_memory_backend() {
+        let backend = InMemoryBackend::new(None);
+        backend
+            .set_many(vec![(1, "one".to_string()), (2, "two".to_string())])
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.get_many(&[2, 3, 1]).await.unwrap(),
+            vec![Some("two".to_string()), None, Some("one".to_string())]
+        );
+
+        backend.remove_many(&[1]).await.unwrap();
+        assert_eq!(backend.get_many(&[1]).await.unwrap(), vec![None]);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_backend_ttl() {
+        let backend = InMemoryBackend::new(Some(Duration::from_millis(0)));
+        backend.set_many(vec![(1, 1)]).await.unwrap();
+        assert_eq!(backend.get_many(&[1]).await.unwrap(), vec![None]);
+    }
+
+    #[cfg(feature = "redis-backend")]
+    mod redis_backend {
+        use super::super::{CacheBackend, RedisBackend};
+        use std::collections::HashMap;
+        use std::sync::{Arc, Mutex};
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+        use tokio::net::{TcpListener, TcpStream};
+
+        type Storage = Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>;
+
+        /// Minimal stand-in for a Redis server, supports only MGET, SET and DEL
+        async fn start_server() -> (String, Storage) {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("redis://{}", listener.local_addr().unwrap());
+            let storage = Storage::default();
+            let server_storage = storage.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (socket, _) = listener.accept().await.unwrap();
+                    tokio::spawn(serve(socket, server_storage.clone()));
+                }
+            });
+            (url, storage)
+        }
+
+        async fn serve(socket: TcpStream, storage: Storage) {
+            let mut socket = BufReader::new(socket);
+            while let Some(args) = read_command(&mut socket).await {
+                let reply = {
+                    let mut storage = storage.lock().unwrap();
+                    match args[0].to_ascii_uppercase().as_slice() {
+                        b"MGET" => {
+                            let mut reply = format!("*{}\r\n", args.len() - 1).into_bytes();
+                            for key in &args[1..] {
+                                match storage.get(key) {
+                                    Some(v) => {
+                                        reply.extend(format!("${}\r\n", v.len()).as_bytes());
+                                        reply.extend(v);
+                                        reply.extend(b"\r\n");
+                                    }
+                                    None => reply.extend(b"$-1\r\n"),
+                                }
+                            }
+                            reply
+                        }
+                        b"SET" => {
+                            storage.insert(args[1].clone(), args[2].clone());
+                            b"+OK\r\n".to_vec()
+                        }
+                        b"DEL" => {
+                            let removed = args[1..]
+                                .iter()
+                                .filter(|k| storage.remove(*k).is_some())
+                                .count();
+                            format!(":{}\r\n", removed).into_bytes()
+                        }
+                        _ => b"-ERR unknown command\r\n".to_vec(),
+                    }
+                };
+                socket.write_all(&reply).await.unwrap();
+            }
+        }
+
+        async fn read_command(socket: &mut BufReader<TcpStream>) -> Option<Vec<Vec<u8>>> {
+            let mut line = String::new();
+            if socket.read_line(&mut line).await.ok()? == 0 {
+                return None;
+            }
+            let argc: usize = line.trim_start_matches('*').trim().parse().ok()?;
+            let mut args = Vec::with_capacity(argc);
+            for _ in 0..argc {
+                line.clear();
+                socket.read_line(&mut line).await.ok()?;
+                let len: usize = line.trim_start_matches('$').trim().parse().ok()?;
+                let mut arg = vec![0; len + 2];
+                socket.read_exact(&mut arg).await.ok()?;
+                arg.truncate(len);
+                args.push(arg);
+            }
+            Some(args)
+        }
+
+        #[tokio::test]
+        async fn test_redis_backend() {
+            let (url, storage) = start_server().await;
+            let backend = RedisBackend::<String, Vec<u32>>::new(&url, "assets", None).unwrap();
+
+            backend
+                .set_many(vec![
+                    ("a".to_string(), vec![1, 2]),
+                    ("b".to_string(), vec![]),
+                ])
+                .await
+                .unwrap();
+            assert_eq!(
+                storage.lock().unwrap().get(&b"assets:\"a\"".to_vec()),
+                Some(&b"[1,2]".to_vec())
+            );
+
+            let keys = ["b".to_string(), "c".to_string(), "a".to_string()];
+            assert_eq!(
+                backend.get_many(&keys).await.unwrap(),
+                vec![Some(vec![]), None, Some(vec![1, 2])]
+            );
+
+            backend.remove_many(&keys[..1]).await.unwrap();
+            assert_eq!(
+                backend.get_many(&keys).await.unwrap(),
+                vec![None, None, Some(vec![1, 2])]
+            );
+        }
+    }
+}
@@ -1,10 +1,12 @@
 use cached::async_mutex::Mutex;
 use dataloader::cached::Cache as DlCache;
 use once_cell::sync::Lazy;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::Instant;
 use typemap::{ShareCloneMap, TypeMap};
 
 static CACHES: Lazy<Mutex<ShareCloneMap>> = Lazy::new(|| Mutex::new(TypeMap::custom()));
@@ -12,13 +14,28 @@ static CACHES: Lazy<Mutex<ShareCloneMap>> = Lazy::new(|| Mutex::new(TypeMap::cus
 pub trait SharedObj: Send + Sync + 'static {}
 pub trait CacheKey: Eq + Hash + Clone + Debug + SharedObj {}
 pub trait CacheVal: Clone + Debug + SharedObj {}
-pub trait CacheBounds<K: CacheKey, V: CacheVal>: cached::Cached<K, V> + SharedObj {}
+/// Cache usable by a `CachedLoader`, implemented for caches of the `cached` crate
+/// and `WeightedCache`, other caches implementing `cached::Cached` can implement it too
+pub trait CacheBounds<K: CacheKey, V: CacheVal>: cached::Cached<K, V> + SharedObj {
+    /// Whether the value just set for the key with `cache_set` was kept,
+    /// checked without counting a hit or changing the order of eviction.
+    ///
+    /// Only caches that may refuse values need to override it
+    #[inline]
+    fn cache_keeps(&self, _key: &K) -> bool {
+        true
+    }
+}
+
 pub trait ErrBounds: Debug + Send {}
 
 impl<T> SharedObj for T where T: Send + Sync + 'static {}
 impl<T> CacheKey for T where T: Eq + Hash + Clone + Debug + SharedObj {}
 impl<T> CacheVal for T where T: Clone + Debug + SharedObj {}
-impl<K: CacheKey, V: CacheVal, T> CacheBounds<K, V> for T where T: cached::Cached<K, V> + SharedObj {}
+impl<K: CacheKey, V: CacheVal> CacheBounds<K, V> for cached::UnboundCache<K, V> {}
+impl<K: CacheKey, V: CacheVal> CacheBounds<K, V> for cached::SizedCache<K, V> {}
+impl<K: CacheKey, V: CacheVal> CacheBounds<K, V> for cached::TimedCache<K, V> {}
+impl<K: CacheKey, V: CacheVal> CacheBounds<K, V> for cached::TimedSizedCache<K, V> {}
 impl<T> ErrBounds for T where T: Debug + Send {}
 
 struct TyMapKey<T>(PhantomData<T>);
@@ -31,6 +48,13 @@ pub struct Cacher<K: CacheKey, V: CacheVal, C: CacheBounds<K, V>> {
     cache: C,
     cache_strategy: Box<dyn Fn(&K, &V) -> bool + Send + 'static>,
     keys_to_drop: Vec<K>,
+    evictions: usize,
+    // expiration of restored entries, which may be shorter than the lifespan of the cache
+    deadlines: HashMap<K, Instant>,
+    // values loaded since the last cleanup, which moves them into the cache,
+    // so that dataloader reads them back even if the cache doesn't keep them,
+    // e.g. when a batch is heavier than a `WeightedCache`
+    loaded: HashMap<K, V>,
 }
 
 impl<K: CacheKey, V: CacheVal, C: CacheBounds<K, V>> DlCache for &mut Cacher<K, V, C> {
@@ -38,21 +62,27 @@ impl<K: CacheKey, V: CacheVal, C: CacheBounds<K, V>> DlCache for &mut Cacher<K,
     type Val = V;
 
     fn get(&mut self, key: &Self::Key) -> Option<&Self::Val> {
+        if self.loaded.contains_key(key) {
+            return self.loaded.get(key);
+        }
+        self.expire(key);
         self.cache.cache_get(key)
     }
 
     fn insert(&mut self, key: Self::Key, val: Self::Val) {
-        if !(self.cache_strategy)(&key, &val) {
-            self.add_key_to_drop(&key)
-        }
-        self.cache.cache_set(key, val);
+        self.deadlines.remove(&key);
+        self.loaded.insert(key, val);
     }
 
     fn remove(&mut self, key: &Self::Key) -> Option<Self::Val> {
+        self.deadlines.remove(key);
+        self.loaded.remove(key);
         self.cache.cache_remove(key)
     }
 
     fn clear(&mut self) {
+        self.deadlines.clear();
+        self.loaded.clear();
         self.cache.cache_clear()
     }
 }
@@ -63,6 +93,9 @@ impl<K: CacheKey, V: CacheVal, C: CacheBounds<K, V>> Cacher<K, V, C> {
             cache,
             cache_strategy: Box::new(strategy_fn),
             keys_to_drop: Vec::new(),
+            evictions: 0,
+            deadlines: HashMap::new(),
+            loaded: HashMap::new(),
         }
     }
 
@@ -80,14 +113,139 @@ impl<K: CacheKey, V: CacheVal, C: CacheBounds<K, V>> Cacher<K, V, C> {
         entry.clone()
     }
 
+    /// Cached value of the key, without loading it
+    pub fn peek(&mut self, key: &K) -> Option<V> {
+        self.expire(key);
+        self.cache.cache_get(key).cloned()
+    }
+
+    /// Remove the key if it was restored with a deadline that has passed
+    fn expire(&mut self, key: &K) {
+        if self.deadlines.is_empty() {
+            return;
+        }
+        if matches!(self.deadlines.get(key), Some(deadline) if *deadline <= Instant::now()) {
+            (&mut *self).remove(key);
+        }
+    }
+
+    fn cache_value(&mut self, key: K, val: V) {
+        if !(self.cache_strategy)(&key, &val) {
+            // the previous value is outdated anyway
+            self.cache.cache_remove(&key);
+            return;
+        }
+        let size_before = self.cache.cache_size();
+        let replaced = self.cache.cache_set(key.clone(), val).is_some();
+        // the cache may refuse the value, which isn't an eviction
+        let kept = self.cache.cache_keeps(&key);
+        let expected_size = size_before + usize::from(!replaced) - usize::from(!kept);
+        self.evictions += expected_size.saturating_sub(self.cache.cache_size());
+    }
+
     pub fn add_key_to_drop(&mut self, key: &K) {
         self.keys_to_drop.push(key.clone())
     }
 
+    /// Number of entries evicted by the underlying cache since the last call
+    pub fn take_evictions(&mut self) -> usize {
+        std::mem::take(&mut self.evictions)
+    }
+
+    pub fn invalidate(&mut self, keys: &[K]) {
+        for key in keys {
+            (&mut *self).remove(key);
+        }
+    }
+
+    pub fn invalidate_all(&mut self) {
+        (&mut *self).clear()
+    }
+
+    pub fn prime(&mut self, key: K, val: V) {
+        (&mut *self).insert(key, val);
+        self.cleanup();
+    }
+
     pub fn cleanup(&mut self) {
+        for (key, val) in std::mem::take(&mut self.loaded) {
+            self.cache_value(key, val);
+        }
         let keys_to_remove = self.keys_to_drop.drain(..).collect::<Vec<K>>();
         for key in keys_to_remove {
             (&mut *self).remove(&key);
         }
     }
 }
+
+#[cfg(feature = "snapshot")]
+impl<K, V, C> Cacher<K, V, C>
+where
+    K: CacheKey,
+    V: CacheVal,
+    C: CacheBounds<K, V> + crate::snapshot::CacheEntries<K, V>,
+{
+    /// Cached entries with their remaining lifetime
+    pub fn entries(&self) -> Vec<(K, V, Option<std::time::Duration>)> {
+        let now = Instant::now();
+        self.cache
+            .entries()
+            .into_iter()
+            .filter_map(|(key, val, ttl)| {
+                let deadline = self
+                    .deadlines
+                    .get(&key)
+                    .map(|deadline| deadline.saturating_duration_since(now));
+                let ttl = match (ttl, deadline) {
+                    (Some(ttl), Some(deadline)) => Some(ttl.min(deadline)),
+                    (ttl, deadline) => ttl.or(deadline),
+                };
+                match ttl {
+                    Some(ttl) if ttl.is_zero() => None,
+                    ttl => Some((key, val, ttl)),
+                }
+            })
+            .collect()
+    }
+
+    /// Put the entry into cache, it expires after `ttl` or the lifespan of the cache,
+    /// whichever comes first. Returns whether the entry was cached
+    pub fn restore(&mut self, key: K, val: V, ttl: Option<std::time::Duration>) -> bool {
+        if !(self.cache_strategy)(&key, &val) {
+            return false;
+        }
+        self.prime(key.clone(), val);
+        // the cache may refuse the value too
+        if !self.cache.cache_keeps(&key) {
+            return false;
+        }
+        if let Some(ttl) = ttl {
+            self.deadlines.insert(key, Instant::now() + ttl);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WeightedCache;
+    use cached::Cached;
+
+    #[test]
+    fn test_loaded_values() {
+        let weigher: fn(&u8, &String) -> usize = |_, v| v.len();
+        let cache = WeightedCache::with_max_weight(4, weigher);
+        let mut cacher = Cacher::new(cache, |_: &u8, _: &String| true);
+        for key in 1..=3 {
+            (&mut cacher).insert(key, format!("v{}", key));
+        }
+        //values are read back before the cache gets them
+        assert_eq!((&mut cacher).get(&3), Some(&"v3".to_string()));
+        cacher.cleanup();
+        assert_eq!(cacher.cache.cache_size(), 2);
+        assert_eq!(cacher.take_evictions(), 1);
+        //caching values isn't a hit
+        assert_eq!(cacher.cache.cache_hits(), Some(0));
+    }
+}
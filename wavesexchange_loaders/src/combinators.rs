@@ -0,0 +1,316 @@
+use crate::cacher::{CacheKey, CacheVal, ErrBounds};
+use crate::error::LoaderError;
+use crate::loaders::{order_values, Loader};
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+
+/// Combinators over any `Loader`, import this trait to use them.
+///
+/// Combined loaders call `load_many` of the inner loaders with all keys at once,
+/// so batching and caching of every inner loader are preserved.
+///
+/// Note that combined loaders aren't `Clone`, wrap them in `Arc` to share.
+pub trait LoaderExt<K, V, E: ErrBounds, const HAS_CACHE: bool>:
+    Loader<K, V, E, HAS_CACHE> + Sized
+{
+    /// Load keys that failed or returned `None` in this loader from `fallback`.
+    ///
+    /// Available for loaders of `Option<_>` values, use `.map(Some)` for other ones.
+    /// If `fallback` fails too, its error is returned
+    fn or_else<B, const B_CACHE: bool>(self, fallback: B) -> OrElse<Self, B, HAS_CACHE, B_CACHE>
+    where
+        B: Loader<K, V, E, B_CACHE>,
+    {
+        OrElse {
+            first: self,
+            fallback,
+        }
+    }
+
+    /// Transform loaded values
+    fn map<F, V2>(self, f: F) -> Map<Self, F, V, HAS_CACHE>
+    where
+        F: Fn(V) -> V2 + Send + Sync,
+    {
+        Map {
+            inner: self,
+            f,
+            _pd: PhantomData,
+        }
+    }
+
+    /// Use loaded values as keys for `next`, e.g. load asset ids, then details of these assets
+    fn then<B, V2, const B_CACHE: bool>(self, next: B) -> Then<Self, B, V, HAS_CACHE, B_CACHE>
+    where
+        B: Loader<V, V2, E, B_CACHE>,
+    {
+        Then {
+            first: self,
+            next,
+            _pd: PhantomData,
+        }
+    }
+}
+
+impl<K, V, E: ErrBounds, L, const HAS_CACHE: bool> LoaderExt<K, V, E, HAS_CACHE> for L where
+    L: Loader<K, V, E, HAS_CACHE>
+{
+}
+
+/// See `LoaderExt::or_else`
+pub struct OrElse<A, B, const A_CACHE: bool, const B_CACHE: bool> {
+    first: A,
+    fallback: B,
+}
+
+#[async_trait]
+impl<K, V, E, A, B, const A_CACHE: bool, const B_CACHE: bool> Loader<K, Option<V>, E, false>
+    for OrElse<A, B, A_CACHE, B_CACHE>
+where
+    K: CacheKey,
+    V: CacheVal,
+    E: ErrBounds,
+    A: Loader<K, Option<V>, E, A_CACHE> + Sync,
+    B: Loader<K, Option<V>, E, B_CACHE> + Sync,
+{
+    async fn load(&self, key: K) -> Result<Option<V>, LoaderError<K, E>> {
+        let mut values = self.load_many(vec![key.clone()]).await?;
+        Ok(values.remove(&key).flatten())
+    }
+
+    async fn load_many(&self, keys: Vec<K>) -> Result<HashMap<K, Option<V>>, LoaderError<K, E>> {
+        let mut values = match self.first.load_many(keys.clone()).await {
+            Ok(values) => values,
+            Err(_) => return self.fallback.load_many(keys).await,
+        };
+        let missing = keys
+            .into_iter()
+            .filter(|k| !matches!(values.get(k), Some(Some(_))))
+            .collect::<HashSet<_>>();
+        if !missing.is_empty() {
+            let fallback_values = self
+                .fallback
+                .load_many(missing.into_iter().collect())
+                .await?;
+            values.extend(fallback_values);
+        }
+        Ok(values)
+    }
+
+    async fn load_many_ordered(&self, keys: Vec<K>) -> Result<Vec<Option<V>>, LoaderError<K, E>> {
+        let values = self.load_many(keys.clone()).await?;
+        order_values(&keys, values)
+    }
+}
+
+/// See `LoaderExt::map`
+pub struct Map<L, F, V, const HAS_CACHE: bool> {
+    inner: L,
+    f: F,
+    _pd: PhantomData<fn() -> V>,
+}
+
+#[async_trait]
+impl<K, V, V2, E, L, F, const HAS_CACHE: bool> Loader<K, V2, E, false> for Map<L, F, V, HAS_CACHE>
+where
+    K: CacheKey,
+    V: CacheVal,
+    V2: CacheVal,
+    E: ErrBounds,
+    L: Loader<K, V, E, HAS_CACHE> + Sync,
+    F: Fn(V) -> V2 + Send + Sync,
+{
+    async fn load(&self, key: K) -> Result<V2, LoaderError<K, E>> {
+        self.inner.load(key).await.map(&self.f)
+    }
+
+    async fn load_many(&self, keys: Vec<K>) -> Result<HashMap<K, V2>, LoaderError<K, E>> {
+        let values = self.inner.load_many(keys).await?;
+        Ok(values.into_iter().map(|(k, v)| (k, (self.f)(v))).collect())
+    }
+
+    async fn load_many_ordered(&self, keys: Vec<K>) -> Result<Vec<V2>, LoaderError<K, E>> {
+        let values = self.inner.load_many_ordered(keys).await?;
+        Ok(values.into_iter().map(&self.f).collect())
+    }
+}
+
+/// See `LoaderExt::then`
+pub struct Then<A, B, V, const A_CACHE: bool, const B_CACHE: bool> {
+    first: A,
+    next: B,
+    _pd: PhantomData<fn() -> V>,
+}
+
+#[async_trait]
+impl<K, V, V2, E, A, B, const A_CACHE: bool, const B_CACHE: bool> Loader<K, V2, E, false>
+    for Then<A, B, V, A_CACHE, B_CACHE>
+where
+    K: CacheKey,
+    V: CacheKey,
+    V2: CacheVal,
+    E: ErrBounds,
+    A: Loader<K, V, E, A_CACHE> + Sync,
+    B: Loader<V, V2, E, B_CACHE> + Sync,
+{
+    async fn load(&self, key: K) -> Result<V2, LoaderError<K, E>> {
+        let next_key = self.first.load(key.clone()).await?;
+        self.next
+            .load(next_key)
+            .await
+            .map_err(|e| e.map_keys(|_| vec![key]))
+    }
+
+    async fn load_many(&self, keys: Vec<K>) -> Result<HashMap<K, V2>, LoaderError<K, E>> {
+        let next_keys = self.first.load_many(keys).await?;
+        let unique_next_keys = next_keys.values().cloned().collect::<HashSet<_>>();
+        let values = match self
+            .next
+            .load_many(unique_next_keys.into_iter().collect())
+            .await
+        {
+            Ok(values) => values,
+            Err(e) => {
+                // report the keys of this loader, not the keys of the next one
+                return Err(e.map_keys(|missing| {
+                    next_keys
+                        .iter()
+                        .filter(|(_, next_key)| missing.contains(next_key))
+                        .map(|(k, _)| k.clone())
+                        .collect()
+                }));
+            }
+        };
+        let missing = next_keys
+            .iter()
+            .filter(|(_, next_key)| !values.contains_key(next_key))
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(LoaderError::KeysMissing(missing));
+        }
+        Ok(next_keys
+            .into_iter()
+            .map(|(k, next_key)| (k, values[&next_key].clone()))
+            .collect())
+    }
+
+    async fn load_many_ordered(&self, keys: Vec<K>) -> Result<Vec<V2>, LoaderError<K, E>> {
+        let values = self.load_many(keys.clone()).await?;
+        order_values(&keys, values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CachedLoader, NonCachedLoader, UnboundCache};
+    use std::sync::Mutex;
+
+    static BATCHES: Mutex<Vec<(&str, Vec<u16>)>> = Mutex::new(Vec::new());
+
+    fn record(loader: &'static str, keys: &[u16]) {
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        BATCHES.lock().unwrap().push((loader, keys));
+    }
+
+    fn take_batches() -> Vec<(&'static str, Vec<u16>)> {
+        std::mem::take(&mut *BATCHES.lock().unwrap())
+    }
+
+    // knows only even keys, fails on zero
+    #[derive(Clone)]
+    struct State;
+
+    #[async_trait]
+    impl CachedLoader<u16, Option<String>> for State {
+        type Cache = UnboundCache<u16, Option<String>>;
+        type Error = String;
+
+        async fn load_fn(&mut self, keys: &[u16]) -> Result<Vec<Option<String>>, String> {
+            record("state", keys);
+            if keys.contains(&0) {
+                return Err("state is down".to_string());
+            }
+            Ok(keys
+                .iter()
+                .map(|k| (k % 2 == 0).then(|| format!("state {}", k)))
+                .collect())
+        }
+
+        fn init_cache() -> Self::Cache {
+            UnboundCache::new()
+        }
+    }
+
+    #[derive(Clone)]
+    struct Node;
+
+    #[async_trait]
+    impl NonCachedLoader<u16, Option<String>> for Node {
+        type Error = String;
+
+        async fn load_fn(&mut self, keys: &[u16]) -> Result<Vec<Option<String>>, String> {
+            record("node", keys);
+            Ok(keys.iter().map(|k| Some(format!("node {}", k))).collect())
+        }
+    }
+
+    // maps key to the id of its parent
+    #[derive(Clone)]
+    struct Parents;
+
+    #[async_trait]
+    impl NonCachedLoader<u16, u16> for Parents {
+        type Error = String;
+
+        async fn load_fn(&mut self, keys: &[u16]) -> Result<Vec<u16>, String> {
+            record("parents", keys);
+            Ok(keys.iter().map(|k| k / 10).collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_combinators() {
+        let loader = State.or_else(Node);
+        assert_eq!(
+            loader.load_many_ordered(vec![2, 3, 4, 5]).await,
+            Ok(vec![
+                Some("state 2".to_string()),
+                Some("node 3".to_string()),
+                Some("state 4".to_string()),
+                Some("node 5".to_string()),
+            ])
+        );
+        assert_eq!(
+            take_batches(),
+            vec![("state", vec![2, 3, 4, 5]), ("node", vec![3, 5])]
+        );
+
+        //values of the first loader are cached, failed batch goes to the fallback entirely
+        assert_eq!(loader.load(2).await, Ok(Some("state 2".to_string())));
+        assert_eq!(loader.load(0).await, Ok(Some("node 0".to_string())));
+        assert_eq!(take_batches(), vec![("state", vec![0]), ("node", vec![0])]);
+
+        let loader = Parents.then(State.map(|v| v.unwrap_or_default()));
+        assert_eq!(
+            loader.load_many_ordered(vec![21, 42, 45, 31]).await,
+            Ok(vec![
+                "state 2".to_string(),
+                "state 4".to_string(),
+                "state 4".to_string(),
+                "".to_string(),
+            ])
+        );
+        //parents are already cached in the state loader
+        assert_eq!(take_batches(), vec![("parents", vec![21, 31, 42, 45])]);
+
+        let loader = Parents.map(|p| p * 2).then(Node.or_else(State));
+        assert_eq!(loader.load(15).await, Ok(Some("node 2".to_string())));
+        assert_eq!(
+            take_batches(),
+            vec![("parents", vec![15]), ("node", vec![2])]
+        );
+    }
+}
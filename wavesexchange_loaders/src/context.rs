@@ -0,0 +1,146 @@
+use crate::cacher::{CacheKey, CacheVal, ErrBounds};
+use crate::error::LoaderError;
+use crate::loaders::{order_values, Loader};
+use cached::async_mutex::Mutex;
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex as SyncMutex};
+
+type RequestCache<K, V> = Arc<Mutex<HashMap<K, V>>>;
+
+/// Request-scoped caches of any number of loaders.
+///
+/// Unlike the global cache of a `CachedLoader`, values loaded through the context
+/// live only as long as the context itself, i.e. one HTTP/GraphQL request:
+/// ```ignore
+/// async fn handler(ctx: LoaderContext) -> ... {
+///     let assets = ctx.scoped(AssetsLoader::new());
+///     // both calls share the same request cache, the asset is loaded once
+///     let (a, b) = join!(assets.load(id.clone()), assets.load(id));
+/// }
+/// ```
+///
+/// The context is cheap to clone, clones share the caches.
+/// See `wavesexchange_warp::loaders::loader_context` for a warp filter creating it.
+#[derive(Clone, Default)]
+pub struct LoaderContext {
+    caches: Arc<SyncMutex<HashMap<TypeId, Box<dyn Any + Send + Sync>>>>,
+}
+
+impl LoaderContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wrap the loader, so that its values are cached in this context.
+    ///
+    /// Works with any `Loader`, global cache of a `CachedLoader` is still used
+    /// for keys missing in the request cache
+    pub fn scoped<L, const HAS_CACHE: bool>(&self, loader: L) -> Scoped<L, HAS_CACHE> {
+        Scoped {
+            ctx: self.clone(),
+            loader,
+        }
+    }
+
+    fn cache<L: 'static, K: CacheKey, V: CacheVal>(&self) -> RequestCache<K, V> {
+        let mut caches = self.caches.lock().unwrap();
+        caches
+            .entry(TypeId::of::<(L, K, V)>())
+            .or_insert_with(|| Box::new(RequestCache::<K, V>::default()))
+            .downcast_ref::<RequestCache<K, V>>()
+            .expect("request cache type is determined by its key")
+            .clone()
+    }
+}
+
+/// Loader cached in a `LoaderContext`, see `LoaderContext::scoped`
+pub struct Scoped<L, const HAS_CACHE: bool> {
+    ctx: LoaderContext,
+    loader: L,
+}
+
+#[async_trait]
+impl<K, V, E, L, const HAS_CACHE: bool> Loader<K, V, E, false> for Scoped<L, HAS_CACHE>
+where
+    K: CacheKey,
+    V: CacheVal,
+    E: ErrBounds,
+    L: Loader<K, V, E, HAS_CACHE> + Send + Sync + 'static,
+{
+    async fn load(&self, key: K) -> Result<V, LoaderError<K, E>> {
+        let mut values = self.load_many(vec![key.clone()]).await?;
+        values
+            .remove(&key)
+            .ok_or(LoaderError::KeysMissing(vec![key]))
+    }
+
+    async fn load_many(&self, keys: Vec<K>) -> Result<HashMap<K, V>, LoaderError<K, E>> {
+        let cache = self.ctx.cache::<L, K, V>();
+        // concurrent loads of the same loader wait for each other to reuse the loaded values
+        let mut cache = cache.lock().await;
+        let missing = keys
+            .iter()
+            .filter(|k| !cache.contains_key(k))
+            .cloned()
+            .collect::<HashSet<_>>();
+        if !missing.is_empty() {
+            let values = self.loader.load_many(missing.into_iter().collect()).await?;
+            cache.extend(values);
+        }
+        Ok(keys
+            .into_iter()
+            .filter_map(|k| cache.get(&k).cloned().map(|v| (k, v)))
+            .collect())
+    }
+
+    async fn load_many_ordered(&self, keys: Vec<K>) -> Result<Vec<V>, LoaderError<K, E>> {
+        let values = self.load_many(keys.clone()).await?;
+        order_values(&keys, values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NonCachedLoader;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static LOADED_KEYS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone)]
+    struct Balances;
+
+    #[async_trait]
+    impl NonCachedLoader<&'static str, u64> for Balances {
+        type Error = ();
+
+        async fn load_fn(&mut self, keys: &[&'static str]) -> Result<Vec<u64>, ()> {
+            LOADED_KEYS.fetch_add(keys.len(), Ordering::SeqCst);
+            Ok(keys.iter().map(|k| k.len() as u64).collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loader_context() {
+        let ctx = LoaderContext::new();
+        let balances = ctx.scoped(Balances);
+        let (a, b) = tokio::join!(
+            balances.load_many_ordered(vec!["alice", "bob"]),
+            balances.load("alice")
+        );
+        assert_eq!(a, Ok(vec![5, 3]));
+        assert_eq!(b, Ok(5));
+        assert_eq!(LOADED_KEYS.swap(0, Ordering::SeqCst), 2);
+
+        //another scoped loader of the same context shares the cache
+        let balances = ctx.clone().scoped(Balances);
+        assert_eq!(balances.load("bob").await, Ok(3));
+        assert_eq!(LOADED_KEYS.swap(0, Ordering::SeqCst), 0);
+
+        //new request, new cache
+        let balances = LoaderContext::new().scoped(Balances);
+        assert_eq!(balances.load("bob").await, Ok(3));
+        assert_eq!(LOADED_KEYS.swap(0, Ordering::SeqCst), 1);
+    }
+}
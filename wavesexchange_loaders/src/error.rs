@@ -1,9 +1,35 @@
 use std::fmt::Debug;
+use std::time::Duration;
 
 #[derive(thiserror::Error, Debug, PartialEq, Eq)]
-pub enum LoaderError<E: Debug> {
-    #[error("{0}; check your load_fn, it should return as many values as keys were provided")]
-    MissingValues(String),
+pub enum LoaderError<K, E: Debug> {
+    #[error("load_fn returned {got} values for {expected} keys; check your load_fn, it should return as many values as keys were provided")]
+    LengthMismatch { expected: usize, got: usize },
+    #[error("No values for keys: {0:?}")]
+    KeysMissing(Vec<K>),
+    #[error("Internal loader error: {0}")]
+    Internal(String),
     #[error("An error encountered: {0}")]
     Other(E),
+    #[error("load_fn didn't complete in {0:?}")]
+    Timeout(Duration),
+    #[error("Circuit breaker is open after repeated load_fn failures")]
+    CircuitOpen,
+}
+
+impl<K, E: Debug> LoaderError<K, E> {
+    /// Convert keys of `KeysMissing`, e.g. when the keys of an inner loader
+    /// differ from the keys of the outer one
+    pub fn map_keys<K2>(self, f: impl FnOnce(Vec<K>) -> Vec<K2>) -> LoaderError<K2, E> {
+        match self {
+            LoaderError::LengthMismatch { expected, got } => {
+                LoaderError::LengthMismatch { expected, got }
+            }
+            LoaderError::KeysMissing(keys) => LoaderError::KeysMissing(f(keys)),
+            LoaderError::Internal(e) => LoaderError::Internal(e),
+            LoaderError::Other(e) => LoaderError::Other(e),
+            LoaderError::Timeout(t) => LoaderError::Timeout(t),
+            LoaderError::CircuitOpen => LoaderError::CircuitOpen,
+        }
+    }
 }
@@ -34,138 +34,145 @@ mod usage {
     let s = SomeLoaderStruct {};
     // result type is listed here just for clarity,
     // the .load() argument type annotation is enough for compiler to infer other types
-    let result: Result<String, LoaderError<MyBeautifulError>> = s.load(5i32);
+    let result: Result<String, LoaderError<i32, MyBeautifulError>> = s.load(5i32);
 }
 ```
 
+Simple loaders can be defined from an async function with the [`loader`] attribute:
+`#[loader(cache = "timed(30)", batch = 100)] async fn load_assets(ctx: &Ctx, ids: &[String]) -> Result<Vec<Asset>, E>`
+generates a `LoadAssets` struct implementing `CachedLoader`.
+
+Loaders can be combined with [`LoaderExt`]: `state.or_else(node)` falls back to another loader
+for failed or `None` values, `.map(f)` transforms values and `ids.then(details)` makes dependent loads.
+
+To cache values only within a single request, wrap loaders into a [`LoaderContext`]
+created per request: `ctx.scoped(loader).load(key)`.
+
+Use `load_many_ordered` to get values in the order of keys, and implement [`NonCachedMapLoader`]
+or [`CachedMapLoader`] with `load_fn_map` if the source doesn't guarantee the order of values.
+
+Cache of a `CachedLoader` can be managed without loading via [`CacheControl`]:
+`invalidate(&key)`, `invalidate_many(&keys)`, `clear()` and `prime(key, value)`.
+Cached values can be read without loading them with `peek(&key)`, `peek_many(&keys)`
+and `is_cached(&key)`.
+
+To bound the memory used by big values, use [`WeightedCache`]: it evicts entries
+by their approximate size given by a [`Weigher`] rather than by their count.
+
+Caches can be filled at startup and refreshed periodically in a background task with [`Warmup`].
+With the `snapshot` feature, they can also be saved to a file on shutdown and restored
+on startup, see `CacheSnapshot`.
+
+Values can also be cached in a storage shared between replicas of a service,
+see `CachedLoader::shared_cache`, [`InMemoryBackend`] and `RedisBackend`
+(enabled with the `redis-backend` feature). The in-process cache is checked first,
+then the shared one, and only then `load_fn` is called.
+
+Calls of `load_fn` can be limited with a timeout, retried with exponential backoff
+and guarded by a circuit breaker, see `load_policy()` and [`LoadPolicy`].
+
+The `testing` feature enables the [`testing`] module with a recording mock loader,
+a controllable clock for timed caches and helpers asserting batches of loaded keys.
+
+Every loader reports cache hits, misses, evictions, batch sizes, `load_fn` latency and errors
+to [`global_metrics()`], which can be rendered for a `/metrics` endpoint with
+`global_metrics().to_prometheus()`. Override `observer()` to send them elsewhere.
+
 */
 
+mod backend;
 mod cacher;
+mod combinators;
+mod context;
 mod error;
 mod loaders;
-
+mod metrics;
+mod policy;
+#[cfg(feature = "snapshot")]
+mod snapshot;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod warmup;
+mod weighted;
+
+#[cfg(feature = "redis-backend")]
+pub use backend::RedisBackend;
+pub use backend::{BackendError, CacheBackend, InMemoryBackend, SharedCache};
 pub use cached::{SizedCache, TimedCache, TimedSizedCache, UnboundCache};
+pub use cacher::CacheBounds;
+pub use combinators::{LoaderExt, Map, OrElse, Then};
+pub use context::{LoaderContext, Scoped};
 pub use error::LoaderError;
-pub use loaders::{CachedLoader, InnerCachedLoader, InnerLoader, Loader, NonCachedLoader};
+pub use loaders::{
+    CacheControl, CachedLoader, CachedMapLoader, InnerCachedLoader, InnerLoader, LoadedValues,
+    Loader, NonCachedLoader, NonCachedMapLoader,
+};
+pub use metrics::{
+    global_metrics, LoaderMetrics, LoaderObserver, LoaderStats, NoopObserver, BATCH_SIZE_BUCKETS,
+    LOAD_DURATION_BUCKETS,
+};
+pub use policy::{CircuitBreakerConfig, LoadPolicy, RetryPolicy};
+#[cfg(feature = "snapshot")]
+pub use snapshot::{CacheEntries, CacheSnapshot, SnapshotError};
+pub use warmup::{RefreshHandle, Warmup, WarmupReport};
+pub use wavesexchange_loaders_derive::loader;
+pub use weighted::{Admission, Weigher, WeightedCache};
+
+#[doc(hidden)]
+pub use async_trait::async_trait;
 
 #[macro_use]
 extern crate async_trait;
 
 #[cfg(test)]
 mod tests {
-    use super::LoaderError;
-    use crate::cacher::{CacheKey, CacheVal};
-    use std::fmt::Debug;
-    use std::future::Future;
-    use std::time::{Duration, Instant};
+    use super::testing::{assert_batches, assert_cached, MockClock, MockTimedCache, Recorder};
+    use super::{Loader, LoaderError};
+    use once_cell::sync::Lazy;
+    use std::time::Duration;
     use tokio::time::sleep;
 
-    //upper border, cached values are usually extracted faster
-    const CACHED_DUR: Duration = Duration::from_millis(1);
-    //we assume that loader functions are slow, so we just sleep in them
-    const SLEEP_DUR: Duration = Duration::from_secs(1);
-
-    fn is_cached(load_time: Duration) -> bool {
-        load_time < CACHED_DUR
-    }
-
-    fn is_not_cached(load_time: Duration) -> bool {
-        load_time >= SLEEP_DUR
-    }
-
-    fn always_valid_duration(_: Duration) -> bool {
-        true
-    }
-
-    async fn _measure<E: Debug + PartialEq + Eq, K: CacheKey, V: CacheVal + Eq>(
-        key: K,
-        expected_val: Result<V, LoaderError<E>>,
-        test_fn: impl Future<Output = Result<V, LoaderError<E>>>,
-        measure_fn: impl Fn(Duration) -> bool,
-    ) -> bool {
-        let now = Instant::now();
-        let result = test_fn.await;
-        let elapsed = now.elapsed();
-        let measurement_is_ok = measure_fn(elapsed);
-        let values_are_eq = match (&result, &expected_val) {
-            (Ok(r), Ok(ev)) => r == ev,
-            (Err(r_err), Err(ev_err)) => r_err == ev_err,
-            _ => false,
-        };
-        if !measurement_is_ok {
-            println!("Error: measure fn failed!");
-        }
-        if !values_are_eq {
-            println!("Error: expected and loaded values are not equal");
-            println!(
-                "key: {:?}, value: {:?}, expected value: {:?}",
-                key, result, expected_val
-            );
-        }
-        values_are_eq && measurement_is_ok
-    }
-
-    async fn measure_load_noncached<
-        E: Debug + Send + Eq,
-        K: CacheKey,
-        V: CacheVal + Eq,
-        L: super::NonCachedLoader<K, V, Error = E>,
-    >(
-        loader: &L,
-        key: K,
-        expected_val: Result<V, LoaderError<E>>,
-        measure_fn: impl Fn(Duration) -> bool,
-    ) -> bool {
-        use super::Loader;
-        _measure(key.clone(), expected_val, loader.load(key), measure_fn).await
-    }
-
-    async fn measure_load<
-        E: Debug + Send + Eq,
-        K: CacheKey,
-        V: CacheVal + Eq,
-        L: super::CachedLoader<K, V, Error = E>,
-    >(
-        loader: &L,
-        key: K,
-        expected_val: Result<V, LoaderError<E>>,
-        measure_fn: impl Fn(Duration) -> bool,
-    ) -> bool {
-        use super::Loader;
-        _measure(key.clone(), expected_val, loader.load(key), measure_fn).await
-    }
-
     #[tokio::test]
     async fn test_timed_cache() {
-        use super::{CachedLoader, TimedCache};
+        use super::CachedLoader;
+
+        static CLOCK: Lazy<MockClock> = Lazy::new(MockClock::new);
 
         #[derive(Clone)]
-        struct Loadable;
+        struct Loadable {
+            recorder: Recorder<u64>,
+        }
 
         #[async_trait]
         impl CachedLoader<u64, String> for Loadable {
-            type Cache = TimedCache<u64, String>;
+            type Cache = MockTimedCache<u64, String>;
             type Error = ();
 
             async fn load_fn(&mut self, keys: &[u64]) -> Result<Vec<String>, Self::Error> {
-                sleep(SLEEP_DUR).await;
-                Ok(keys.into_iter().map(|k| format!("num: {}", k)).collect())
+                self.recorder.record(keys);
+                Ok(keys.iter().map(|k| format!("num: {}", k)).collect())
             }
 
             fn init_cache() -> Self::Cache {
-                TimedCache::with_lifespan(3) //seconds to persist in cache
+                MockTimedCache::with_lifespan(&CLOCK, 3) //seconds to persist in cache
             }
         }
 
-        let loader = Loadable {};
-        assert!(measure_load(&loader, 4, Ok("num: 4".to_string()), is_not_cached).await);
+        let loader = Loadable {
+            recorder: Recorder::new(),
+        };
+        let value = assert_batches(&loader.recorder, vec![vec![4]], loader.load(4)).await;
+        assert_eq!(value, Ok("num: 4".to_string()));
 
         //value is cached
-        assert!(measure_load(&loader, 4, Ok("num: 4".to_string()), is_cached).await);
-        sleep(Duration::from_secs(3)).await;
+        CLOCK.advance(Duration::from_secs(2));
+        let value = assert_cached(&loader.recorder, loader.load(4)).await;
+        assert_eq!(value, Ok("num: 4".to_string()));
 
         //value is dropped due to ttl
-        assert!(measure_load(&loader, 4, Ok("num: 4".to_string()), is_not_cached).await);
+        CLOCK.advance(Duration::from_secs(1));
+        let value = assert_batches(&loader.recorder, vec![vec![4]], loader.load(4)).await;
+        assert_eq!(value, Ok("num: 4".to_string()));
     }
 
     #[tokio::test]
@@ -173,7 +180,9 @@ mod tests {
         use super::{CachedLoader, SizedCache};
 
         #[derive(Clone)]
-        struct Loadable;
+        struct Loadable {
+            recorder: Recorder<isize>,
+        }
 
         #[async_trait]
         impl CachedLoader<isize, String> for Loadable {
@@ -181,8 +190,8 @@ mod tests {
             type Error = ();
 
             async fn load_fn(&mut self, keys: &[isize]) -> Result<Vec<String>, Self::Error> {
-                sleep(SLEEP_DUR).await;
-                Ok(keys.into_iter().map(|k| format!("num: {}", k)).collect())
+                self.recorder.record(keys);
+                Ok(keys.iter().map(|k| format!("num: {}", k)).collect())
             }
 
             fn init_cache() -> Self::Cache {
@@ -190,34 +199,26 @@ mod tests {
             }
         }
 
-        let loader = Loadable {};
-        assert!(
-            measure_load(
-                &loader,
-                -65535,
-                Ok("num: -65535".to_string()),
-                is_not_cached
-            )
-            .await
-        );
+        let loader = Loadable {
+            recorder: Recorder::new(),
+        };
+        let recorder = &loader.recorder;
+        let value = assert_batches(recorder, vec![vec![-65535]], loader.load(-65535)).await;
+        assert_eq!(value, Ok("num: -65535".to_string()));
 
         //value is cached
-        assert!(measure_load(&loader, -65535, Ok("num: -65535".to_string()), is_cached).await);
+        let value = assert_cached(recorder, loader.load(-65535)).await;
+        assert_eq!(value, Ok("num: -65535".to_string()));
 
         //rewriting the only available cache cell
-        assert!(measure_load(&loader, -4, Ok("num: -4".to_string()), is_not_cached).await);
-        assert!(measure_load(&loader, -4, Ok("num: -4".to_string()), is_cached).await);
+        let value = assert_batches(recorder, vec![vec![-4]], loader.load(-4)).await;
+        assert_eq!(value, Ok("num: -4".to_string()));
+        let value = assert_cached(recorder, loader.load(-4)).await;
+        assert_eq!(value, Ok("num: -4".to_string()));
 
         //first value is dropped because there can be only one
-        assert!(
-            measure_load(
-                &loader,
-                -65535,
-                Ok("num: -65535".to_string()),
-                is_not_cached
-            )
-            .await
-        );
+        let value = assert_batches(recorder, vec![vec![-65535]], loader.load(-65535)).await;
+        assert_eq!(value, Ok("num: -65535".to_string()));
     }
 
     #[tokio::test]
@@ -225,7 +226,9 @@ mod tests {
         use super::{CachedLoader, UnboundCache};
 
         #[derive(Clone)]
-        struct Loadable;
+        struct Loadable {
+            recorder: Recorder<isize>,
+        }
 
         #[async_trait]
         impl CachedLoader<isize, Option<String>> for Loadable {
@@ -236,9 +239,9 @@ mod tests {
                 &mut self,
                 keys: &[isize],
             ) -> Result<Vec<Option<String>>, Self::Error> {
-                sleep(SLEEP_DUR).await;
+                self.recorder.record(keys);
                 Ok(keys
-                    .into_iter()
+                    .iter()
                     .map(|k| {
                         if k % 2 == 0 {
                             // loader fn returns string only with even numbers
@@ -260,17 +263,24 @@ mod tests {
         }
 
         //even number
-        let loader = Loadable {};
-        assert!(measure_load(&loader, 28, Ok(Some("num: 28".to_string())), is_not_cached).await);
+        let loader = Loadable {
+            recorder: Recorder::new(),
+        };
+        let recorder = &loader.recorder;
+        let value = assert_batches(recorder, vec![vec![28]], loader.load(28)).await;
+        assert_eq!(value, Ok(Some("num: 28".to_string())));
 
         //is cached
-        assert!(measure_load(&loader, 28, Ok(Some("num: 28".to_string())), is_cached).await);
+        let value = assert_cached(recorder, loader.load(28)).await;
+        assert_eq!(value, Ok(Some("num: 28".to_string())));
 
         //odd number
-        assert!(measure_load(&loader, 5, Ok(None), is_not_cached).await);
+        let value = assert_batches(recorder, vec![vec![5]], loader.load(5)).await;
+        assert_eq!(value, Ok(None));
 
         //is not cached
-        assert!(measure_load(&loader, 5, Ok(None), is_not_cached).await);
+        let value = assert_batches(recorder, vec![vec![5]], loader.load(5)).await;
+        assert_eq!(value, Ok(None));
     }
 
     #[tokio::test]
@@ -278,15 +288,17 @@ mod tests {
         use super::{InnerLoader, NonCachedLoader};
 
         #[derive(Clone)]
-        struct Loadable;
+        struct Loadable {
+            recorder: Recorder<i32>,
+        }
 
         #[async_trait]
         impl NonCachedLoader<i32, i64> for Loadable {
             type Error = ();
 
             async fn load_fn(&mut self, keys: &[i32]) -> Result<Vec<i64>, Self::Error> {
-                sleep(SLEEP_DUR).await;
-                Ok(keys.into_iter().cloned().map(i64::from).collect())
+                self.recorder.record(keys);
+                Ok(keys.iter().cloned().map(i64::from).collect())
             }
 
             fn init_loader(loader: InnerLoader<i32, i64, Self>) -> InnerLoader<i32, i64, Self> {
@@ -294,9 +306,21 @@ mod tests {
             }
         }
 
-        let loader = Loadable {};
-        assert!(measure_load_noncached(&loader, 5555, Ok(5555), is_not_cached).await);
-        assert!(measure_load_noncached(&loader, 5555, Ok(5555), is_not_cached).await);
+        let loader = Loadable {
+            recorder: Recorder::new(),
+        };
+        let recorder = &loader.recorder;
+        let value = assert_batches(recorder, vec![vec![5555]], loader.load(5555)).await;
+        assert_eq!(value, Ok(5555));
+        let value = assert_batches(recorder, vec![vec![5555]], loader.load(5555)).await;
+        assert_eq!(value, Ok(5555));
+
+        //keys are split into batches of the max size
+        recorder.take();
+        loader.load_many(vec![1, 2, 3]).await.unwrap();
+        let batches = recorder.take();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches.concat().len(), 3);
     }
 
     #[tokio::test]
@@ -304,15 +328,17 @@ mod tests {
         use super::{CachedLoader, UnboundCache};
 
         #[derive(Clone)]
-        struct Loadable;
+        struct Loadable {
+            recorder: Recorder<isize>,
+        }
 
         #[async_trait]
         impl CachedLoader<isize, ()> for Loadable {
             type Cache = UnboundCache<isize, ()>;
             type Error = String;
 
-            async fn load_fn(&mut self, _keys: &[isize]) -> Result<Vec<()>, Self::Error> {
-                sleep(SLEEP_DUR).await;
+            async fn load_fn(&mut self, keys: &[isize]) -> Result<Vec<()>, Self::Error> {
+                self.recorder.record(keys);
                 Err("oh, no!".to_string())
             }
 
@@ -321,27 +347,16 @@ mod tests {
             }
         }
 
-        let loader = Loadable {};
-        assert!(
-            measure_load(
-                &loader,
-                12345,
-                Err(LoaderError::Other("oh, no!".to_string())),
-                is_not_cached
-            )
-            .await
-        );
+        let loader = Loadable {
+            recorder: Recorder::new(),
+        };
+        let recorder = &loader.recorder;
+        let result = assert_batches(recorder, vec![vec![12345]], loader.load(12345)).await;
+        assert_eq!(result, Err(LoaderError::Other("oh, no!".to_string())));
 
         //not caching errors
-        assert!(
-            measure_load(
-                &loader,
-                12345,
-                Err(LoaderError::Other("oh, no!".to_string())),
-                is_not_cached
-            )
-            .await
-        );
+        let result = assert_batches(recorder, vec![vec![12345]], loader.load(12345)).await;
+        assert_eq!(result, Err(LoaderError::Other("oh, no!".to_string())));
     }
 
     #[tokio::test]
@@ -360,18 +375,376 @@ mod tests {
             }
         }
 
+        assert_eq!(
+            Loadable.load(12345).await,
+            Err(LoaderError::LengthMismatch {
+                expected: 1,
+                got: 0
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        use super::{global_metrics, CachedLoader, Loader, UnboundCache};
+        use std::any::type_name;
+
+        #[derive(Clone)]
+        struct MeteredLoader;
+
+        #[async_trait]
+        impl CachedLoader<u8, u32> for MeteredLoader {
+            type Cache = UnboundCache<u8, u32>;
+            type Error = ();
+
+            async fn load_fn(&mut self, keys: &[u8]) -> Result<Vec<u32>, Self::Error> {
+                Ok(keys.iter().map(|k| *k as u32).collect())
+            }
+
+            fn init_cache() -> Self::Cache {
+                UnboundCache::new()
+            }
+        }
+
+        let loader = MeteredLoader {};
+        loader.load_many(vec![1, 2, 3]).await.unwrap();
+        loader.load_many(vec![1, 2, 3, 4, 4]).await.unwrap();
+
+        let stats = global_metrics()
+            .stats(type_name::<MeteredLoader>())
+            .unwrap();
+        assert_eq!(stats.cache_hits, 3);
+        assert_eq!(stats.cache_misses, 4);
+        assert_eq!(stats.batches, 2);
+        assert_eq!(stats.batch_keys, 4);
+        assert_eq!(stats.errors, 0);
+    }
+
+    #[tokio::test]
+    async fn test_cache_control() {
+        use super::{CacheControl, CachedLoader, Loader, UnboundCache};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Clone)]
+        struct Loadable;
+
+        #[async_trait]
+        impl CachedLoader<i16, i16> for Loadable {
+            type Cache = UnboundCache<i16, i16>;
+            type Error = ();
+
+            async fn load_fn(&mut self, keys: &[i16]) -> Result<Vec<i16>, Self::Error> {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+                Ok(keys.to_vec())
+            }
+
+            fn init_cache() -> Self::Cache {
+                UnboundCache::new()
+            }
+        }
+
         let loader = Loadable {};
-        assert!(
-            measure_load_noncached(
-                &loader,
-                12345,
-                Err(LoaderError::MissingValues(
-                    "Keys and values vectors aren't length-equal! keys: [12345] ;;; values: []"
-                        .to_string()
-                )),
-                always_valid_duration
-            )
-            .await
+        assert_eq!(loader.load(1).await, Ok(1));
+        assert_eq!(loader.load(1).await, Ok(1));
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+        //invalidated key is loaded again
+        loader.invalidate(&1).await;
+        assert_eq!(loader.load(1).await, Ok(1));
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+
+        //primed key is not loaded
+        loader.prime(5, 55).await;
+        assert_eq!(loader.load(5).await, Ok(55));
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+
+        loader.invalidate_many(&[1, 5]).await;
+        assert_eq!(loader.load(5).await, Ok(5));
+        assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+
+        //all keys are loaded after clear
+        loader.clear().await;
+        assert_eq!(loader.load_many(vec![1, 5]).await.unwrap().len(), 2);
+        assert_eq!(CALLS.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_peek() {
+        use super::{CacheControl, CachedLoader, Loader, SizedCache};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Clone)]
+        struct Peekable;
+
+        #[async_trait]
+        impl CachedLoader<i16, u16> for Peekable {
+            type Cache = SizedCache<i16, u16>;
+            type Error = ();
+
+            async fn load_fn(&mut self, keys: &[i16]) -> Result<Vec<u16>, Self::Error> {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+                Ok(keys.iter().map(|k| k.unsigned_abs()).collect())
+            }
+
+            fn init_cache() -> Self::Cache {
+                SizedCache::with_size(2)
+            }
+        }
+
+        let loader = Peekable;
+        assert_eq!(loader.peek(&-1).await, None);
+        assert!(!loader.is_cached(&-1).await);
+        assert_eq!(CALLS.load(Ordering::SeqCst), 0);
+
+        assert_eq!(loader.load_many_ordered(vec![-1, 2]).await, Ok(vec![1, 2]));
+        assert_eq!(loader.peek(&-1).await, Some(1));
+        assert!(loader.is_cached(&2).await);
+        assert_eq!(
+            loader.peek_many(&[-1, 2, 3]).await,
+            std::collections::HashMap::from([(-1, 1), (2, 2)])
+        );
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+        //evicted keys aren't cached anymore
+        loader.load(3).await.unwrap();
+        assert_eq!(loader.peek_many(&[-1, 2, 3]).await.len(), 2);
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shared_cache() {
+        use super::{
+            global_metrics, CacheControl, CachedLoader, InMemoryBackend, Loader, SharedCache,
+            SizedCache, UnboundCache,
+        };
+        use once_cell::sync::Lazy;
+        use std::any::type_name;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static SHARED: Lazy<SharedCache<u32, String>> =
+            Lazy::new(|| Arc::new(InMemoryBackend::new(None)));
+
+        async fn load_fn(keys: &[u32]) -> Result<Vec<String>, ()> {
+            CALLS.fetch_add(keys.len(), Ordering::SeqCst);
+            Ok(keys.iter().map(|k| k.to_string()).collect())
+        }
+
+        // loaders with different in-process caches, as if they were in different replicas
+        #[derive(Clone)]
+        struct FirstReplica;
+
+        #[async_trait]
+        impl CachedLoader<u32, String> for FirstReplica {
+            type Cache = UnboundCache<u32, String>;
+            type Error = ();
+
+            async fn load_fn(&mut self, keys: &[u32]) -> Result<Vec<String>, Self::Error> {
+                load_fn(keys).await
+            }
+
+            fn init_cache() -> Self::Cache {
+                UnboundCache::new()
+            }
+
+            fn shared_cache(&self) -> Option<SharedCache<u32, String>> {
+                Some(SHARED.clone())
+            }
+        }
+
+        #[derive(Clone)]
+        struct SecondReplica;
+
+        #[async_trait]
+        impl CachedLoader<u32, String> for SecondReplica {
+            type Cache = SizedCache<u32, String>;
+            type Error = ();
+
+            async fn load_fn(&mut self, keys: &[u32]) -> Result<Vec<String>, Self::Error> {
+                load_fn(keys).await
+            }
+
+            fn init_cache() -> Self::Cache {
+                SizedCache::with_size(10)
+            }
+
+            fn shared_cache(&self) -> Option<SharedCache<u32, String>> {
+                Some(SHARED.clone())
+            }
+        }
+
+        FirstReplica.load_many(vec![1, 2]).await.unwrap();
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+
+        //values loaded by the first replica are taken from the shared cache
+        let values = SecondReplica.load_many(vec![1, 2, 3]).await.unwrap();
+        assert_eq!(values[&1], "1");
+        assert_eq!(values[&3], "3");
+        assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+
+        //invalidation removes the key from the shared cache too
+        FirstReplica.invalidate(&3).await;
+        assert_eq!(SHARED.get_many(&[3]).await.unwrap(), vec![None]);
+
+        FirstReplica.prime(4, "four".to_string()).await;
+        assert_eq!(SecondReplica.load(4).await.unwrap(), "four");
+        assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+
+        //shared cache hits aren't counted as in-process cache hits
+        let stats = global_metrics()
+            .stats(type_name::<SecondReplica>())
+            .unwrap();
+        assert_eq!(stats.shared_cache_hits, 3);
+        assert_eq!(stats.cache_hits, 0);
+        assert_eq!(stats.cache_misses, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_load_policy() {
+        use super::{
+            global_metrics, CircuitBreakerConfig, LoadPolicy, Loader, LoaderError, NonCachedLoader,
+            RetryPolicy,
+        };
+        use std::any::type_name;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        // fails every call until the counter reaches `failures`, hangs on key 0
+        #[derive(Clone)]
+        struct Flaky {
+            failures: usize,
+        }
+
+        #[async_trait]
+        impl NonCachedLoader<u16, u16> for Flaky {
+            type Error = &'static str;
+
+            async fn load_fn(&mut self, keys: &[u16]) -> Result<Vec<u16>, Self::Error> {
+                if keys.contains(&0) {
+                    sleep(Duration::from_secs(1)).await;
+                }
+                if CALLS.fetch_add(1, Ordering::SeqCst) < self.failures {
+                    return Err("upstream is down");
+                }
+                Ok(keys.to_vec())
+            }
+
+            fn load_policy() -> LoadPolicy {
+                LoadPolicy {
+                    timeout: Some(Duration::from_millis(50)),
+                    retry: Some(RetryPolicy::exponential(2, Duration::from_millis(1))),
+                    circuit_breaker: Some(CircuitBreakerConfig {
+                        failure_threshold: 2,
+                        reset_timeout: Duration::from_secs(60),
+                    }),
+                }
+            }
+
+            fn is_retryable(e: &Self::Error) -> bool {
+                *e != "bad request"
+            }
+        }
+
+        //succeeds on the last retry
+        assert_eq!(Flaky { failures: 2 }.load(1).await, Ok(1));
+        assert_eq!(CALLS.swap(0, Ordering::SeqCst), 3);
+
+        //every attempt times out
+        assert_eq!(
+            Flaky { failures: 0 }.load(0).await,
+            Err(LoaderError::Timeout(Duration::from_millis(50)))
+        );
+        assert_eq!(CALLS.swap(0, Ordering::SeqCst), 0);
+
+        //retries are exhausted for the second time in a row, so the circuit opens
+        assert_eq!(
+            Flaky { failures: 10 }.load(1).await,
+            Err(LoaderError::Other("upstream is down"))
+        );
+        assert_eq!(CALLS.swap(0, Ordering::SeqCst), 3);
+        let batches = global_metrics()
+            .stats(type_name::<Flaky>())
+            .unwrap()
+            .batches;
+        assert_eq!(
+            Flaky { failures: 0 }.load(1).await,
+            Err(LoaderError::CircuitOpen)
+        );
+        assert_eq!(CALLS.load(Ordering::SeqCst), 0);
+        // the rejected call isn't reported as a failed batch
+        let stats = global_metrics().stats(type_name::<Flaky>()).unwrap();
+        assert_eq!(stats.batches, batches);
+    }
+
+    #[tokio::test]
+    async fn test_load_many_ordered() {
+        use super::{CachedLoader, Loader, NonCachedMapLoader, UnboundCache};
+        use std::collections::HashMap;
+
+        // values come from an unordered source
+        #[derive(Clone)]
+        struct Unordered;
+
+        #[async_trait]
+        impl NonCachedMapLoader<u8, String> for Unordered {
+            type Error = ();
+
+            async fn load_fn_map(&mut self, keys: &[u8]) -> Result<HashMap<u8, String>, ()> {
+                Ok(keys
+                    .iter()
+                    .filter(|k| **k != 0)
+                    .map(|k| (*k, k.to_string()))
+                    .collect())
+            }
+        }
+
+        #[derive(Clone)]
+        struct Ordered;
+
+        #[async_trait]
+        impl CachedLoader<u8, i8> for Ordered {
+            type Cache = UnboundCache<u8, i8>;
+            type Error = ();
+
+            async fn load_fn(&mut self, keys: &[u8]) -> Result<Vec<i8>, ()> {
+                Ok(keys.iter().map(|k| -(*k as i8)).collect())
+            }
+
+            fn init_cache() -> Self::Cache {
+                UnboundCache::new()
+            }
+        }
+
+        assert_eq!(
+            Unordered.load_many_ordered(vec![3, 1, 3, 2]).await,
+            Ok(vec![
+                "3".to_string(),
+                "1".to_string(),
+                "3".to_string(),
+                "2".to_string()
+            ])
+        );
+        assert_eq!(Unordered.load(7).await, Ok("7".to_string()));
+        assert!(matches!(
+            Unordered.load_many_ordered(vec![1, 0]).await,
+            Err(LoaderError::KeysMissing(keys)) if keys == vec![0]
+        ));
+
+        assert_eq!(
+            Ordered.load_many_ordered(vec![5, 4, 5]).await,
+            Ok(vec![-5, -4, -5])
+        );
+        //partially cached
+        assert_eq!(
+            Ordered.load_many_ordered(vec![6, 4]).await,
+            Ok(vec![-6, -4])
         );
+        assert_eq!(Ordered.load_many_ordered(vec![]).await, Ok(vec![]));
     }
 }
@@ -1,8 +1,13 @@
+use crate::backend::SharedCache;
 use crate::cacher::{CacheBounds, CacheKey, CacheVal, Cacher, ErrBounds, SharedObj};
 use crate::error::LoaderError;
+use crate::metrics::{global_metrics, LoaderObserver};
+use crate::policy::{call_with_policy, LoadPolicy};
 use dataloader::{cached, non_cached, BatchFn};
-use std::collections::HashMap;
+use std::any::{type_name, TypeId};
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
+use std::time::Instant;
 
 pub type InnerLoader<'b, K, V, L> = non_cached::Loader<
     K,
@@ -33,8 +38,38 @@ pub trait NonCachedLoader<K: CacheKey, V: CacheVal>: SharedObj + Clone {
     /// Setup loader function.  
     ///
     /// It is important to return as many values as keys were provided,
-    /// otherwise dataloader wouldn't process them and return `LoaderError::MissingValues`
+    /// otherwise dataloader wouldn't process them and return `LoaderError::LengthMismatch`
+    ///
+    /// Implement `NonCachedMapLoader` instead if the source can't guarantee the order of values
     async fn load_fn(&mut self, keys: &[K]) -> Result<Vec<V>, Self::Error>;
+
+    #[doc(hidden)]
+    async fn load_values(&mut self, keys: &[K]) -> Result<LoadedValues<K, V>, Self::Error> {
+        self.load_fn(keys).await.map(LoadedValues::Ordered)
+    }
+
+    /// Setup timeout, retries and circuit breaking of `load_fn` calls
+    ///
+    /// Everything is disabled by default
+    #[inline]
+    fn load_policy() -> LoadPolicy {
+        LoadPolicy::default()
+    }
+
+    /// Determine errors of `load_fn` that are worth retrying,
+    /// only they are counted as circuit breaker failures
+    #[inline]
+    fn is_retryable(_: &Self::Error) -> bool {
+        true
+    }
+
+    /// Setup observer that receives batch sizes, `load_fn` latency and errors
+    ///
+    /// Reports to `global_metrics()` by default
+    #[inline]
+    fn observer() -> &'static dyn LoaderObserver {
+        global_metrics()
+    }
 }
 
 #[async_trait]
@@ -58,9 +93,31 @@ pub trait CachedLoader<K: CacheKey, V: CacheVal>: SharedObj + Clone {
     /// Setup loader function.  
     ///
     /// It is important to return as many values as keys were provided,
-    /// otherwise dataloader wouldn't process them and return `LoaderError::MissingValues`
+    /// otherwise dataloader wouldn't process them and return `LoaderError::LengthMismatch`
+    ///
+    /// Implement `CachedMapLoader` instead if the source can't guarantee the order of values
     async fn load_fn(&mut self, keys: &[K]) -> Result<Vec<V>, Self::Error>;
 
+    #[doc(hidden)]
+    async fn load_values(&mut self, keys: &[K]) -> Result<LoadedValues<K, V>, Self::Error> {
+        self.load_fn(keys).await.map(LoadedValues::Ordered)
+    }
+
+    /// Setup timeout, retries and circuit breaking of `load_fn` calls
+    ///
+    /// Everything is disabled by default
+    #[inline]
+    fn load_policy() -> LoadPolicy {
+        LoadPolicy::default()
+    }
+
+    /// Determine errors of `load_fn` that are worth retrying,
+    /// only they are counted as circuit breaker failures
+    #[inline]
+    fn is_retryable(_: &Self::Error) -> bool {
+        true
+    }
+
     /// Setup cache params
     ///
     /// See params for all caches [`here`](https://docs.rs/cached/latest/cached/#structs)
@@ -71,15 +128,230 @@ pub trait CachedLoader<K: CacheKey, V: CacheVal>: SharedObj + Clone {
     fn cache_strategy(_: &K, _: &V) -> bool {
         true
     }
+
+    /// Setup cache shared between replicas, e.g. `RedisBackend`
+    ///
+    /// It is checked for keys missing in the in-process cache before calling `load_fn`,
+    /// loaded values are written to both caches according to `cache_strategy`.
+    /// Shared cache errors are reported to the observer, and the keys are loaded with `load_fn`
+    #[inline]
+    fn shared_cache(&self) -> Option<SharedCache<K, V>> {
+        None
+    }
+
+    /// Setup observer that receives cache hits, misses, evictions,
+    /// batch sizes, `load_fn` latency and errors
+    ///
+    /// Reports to `global_metrics()` by default
+    #[inline]
+    fn observer() -> &'static dyn LoaderObserver {
+        global_metrics()
+    }
+}
+
+/// Values of a batch, in the order of keys or by key
+#[doc(hidden)]
+pub enum LoadedValues<K, V> {
+    Ordered(Vec<V>),
+    ByKey(HashMap<K, V>),
+}
+
+/// `NonCachedLoader` for sources that can't guarantee the order of values,
+/// it's implemented for every `NonCachedMapLoader`
+#[async_trait]
+pub trait NonCachedMapLoader<K: CacheKey, V: CacheVal>: SharedObj + Clone {
+    /// Setup error type for `Loader::load` method
+    type Error: ErrBounds;
+
+    /// Modify loader params, see `NonCachedLoader::init_loader`
+    #[inline]
+    fn init_loader(loader: InnerLoader<K, V, Self>) -> InnerLoader<K, V, Self>
+    where
+        Self: NonCachedLoader<K, V>,
+    {
+        loader
+    }
+
+    /// Setup loader function returning values by key.  
+    ///
+    /// Keys without values result in `LoaderError::KeysMissing`
+    async fn load_fn_map(&mut self, keys: &[K]) -> Result<HashMap<K, V>, Self::Error>;
+
+    /// Setup timeout, retries and circuit breaking of `load_fn_map` calls,
+    /// see `NonCachedLoader::load_policy`
+    #[inline]
+    fn load_policy() -> LoadPolicy {
+        LoadPolicy::default()
+    }
+
+    /// Determine errors of `load_fn_map` that are worth retrying
+    #[inline]
+    fn is_retryable(_: &Self::Error) -> bool {
+        true
+    }
+
+    /// Setup observer, see `NonCachedLoader::observer`
+    #[inline]
+    fn observer() -> &'static dyn LoaderObserver {
+        global_metrics()
+    }
+}
+
+#[async_trait]
+impl<K, V, L> NonCachedLoader<K, V> for L
+where
+    K: CacheKey,
+    V: CacheVal,
+    L: NonCachedMapLoader<K, V>,
+{
+    type Error = <L as NonCachedMapLoader<K, V>>::Error;
+
+    fn init_loader(loader: InnerLoader<K, V, Self>) -> InnerLoader<K, V, Self> {
+        <L as NonCachedMapLoader<K, V>>::init_loader(loader)
+    }
+
+    /// Values of keys in their order, keys without values are skipped
+    async fn load_fn(&mut self, keys: &[K]) -> Result<Vec<V>, Self::Error> {
+        let values = self.load_fn_map(keys).await?;
+        Ok(keys.iter().filter_map(|k| values.get(k).cloned()).collect())
+    }
+
+    async fn load_values(&mut self, keys: &[K]) -> Result<LoadedValues<K, V>, Self::Error> {
+        self.load_fn_map(keys).await.map(LoadedValues::ByKey)
+    }
+
+    fn load_policy() -> LoadPolicy {
+        <L as NonCachedMapLoader<K, V>>::load_policy()
+    }
+
+    fn is_retryable(e: &Self::Error) -> bool {
+        <L as NonCachedMapLoader<K, V>>::is_retryable(e)
+    }
+
+    fn observer() -> &'static dyn LoaderObserver {
+        <L as NonCachedMapLoader<K, V>>::observer()
+    }
+}
+
+/// `CachedLoader` for sources that can't guarantee the order of values,
+/// it's implemented for every `CachedMapLoader`
+#[async_trait]
+pub trait CachedMapLoader<K: CacheKey, V: CacheVal>: SharedObj + Clone {
+    /// Setup cache that will be used, see `CachedLoader::Cache`
+    type Cache: CacheBounds<K, V>;
+
+    /// Setup error type for `Loader::load` method
+    type Error: ErrBounds;
+
+    /// Modify loader params, see `CachedLoader::init_loader`
+    #[inline]
+    fn init_loader(loader: InnerCachedLoader<K, V, Self>) -> InnerCachedLoader<K, V, Self>
+    where
+        Self: CachedLoader<K, V>,
+    {
+        loader
+    }
+
+    /// Setup loader function returning values by key.  
+    ///
+    /// Keys without values result in `LoaderError::KeysMissing`
+    async fn load_fn_map(&mut self, keys: &[K]) -> Result<HashMap<K, V>, Self::Error>;
+
+    /// Setup timeout, retries and circuit breaking of `load_fn_map` calls,
+    /// see `CachedLoader::load_policy`
+    #[inline]
+    fn load_policy() -> LoadPolicy {
+        LoadPolicy::default()
+    }
+
+    /// Determine errors of `load_fn_map` that are worth retrying
+    #[inline]
+    fn is_retryable(_: &Self::Error) -> bool {
+        true
+    }
+
+    /// Setup cache params
+    fn init_cache() -> Self::Cache;
+
+    /// Determine values that will be cached, see `CachedLoader::cache_strategy`
+    #[inline]
+    fn cache_strategy(_: &K, _: &V) -> bool {
+        true
+    }
+
+    /// Setup cache shared between replicas, see `CachedLoader::shared_cache`
+    #[inline]
+    fn shared_cache(&self) -> Option<SharedCache<K, V>> {
+        None
+    }
+
+    /// Setup observer, see `CachedLoader::observer`
+    #[inline]
+    fn observer() -> &'static dyn LoaderObserver {
+        global_metrics()
+    }
+}
+
+#[async_trait]
+impl<K, V, L> CachedLoader<K, V> for L
+where
+    K: CacheKey,
+    V: CacheVal,
+    L: CachedMapLoader<K, V>,
+{
+    type Cache = <L as CachedMapLoader<K, V>>::Cache;
+    type Error = <L as CachedMapLoader<K, V>>::Error;
+
+    fn init_loader(loader: InnerCachedLoader<K, V, Self>) -> InnerCachedLoader<K, V, Self> {
+        <L as CachedMapLoader<K, V>>::init_loader(loader)
+    }
+
+    /// Values of keys in their order, keys without values are skipped
+    async fn load_fn(&mut self, keys: &[K]) -> Result<Vec<V>, Self::Error> {
+        let values = self.load_fn_map(keys).await?;
+        Ok(keys.iter().filter_map(|k| values.get(k).cloned()).collect())
+    }
+
+    async fn load_values(&mut self, keys: &[K]) -> Result<LoadedValues<K, V>, Self::Error> {
+        self.load_fn_map(keys).await.map(LoadedValues::ByKey)
+    }
+
+    fn load_policy() -> LoadPolicy {
+        <L as CachedMapLoader<K, V>>::load_policy()
+    }
+
+    fn is_retryable(e: &Self::Error) -> bool {
+        <L as CachedMapLoader<K, V>>::is_retryable(e)
+    }
+
+    fn init_cache() -> Self::Cache {
+        <L as CachedMapLoader<K, V>>::init_cache()
+    }
+
+    fn cache_strategy(key: &K, value: &V) -> bool {
+        <L as CachedMapLoader<K, V>>::cache_strategy(key, value)
+    }
+
+    fn shared_cache(&self) -> Option<SharedCache<K, V>> {
+        <L as CachedMapLoader<K, V>>::shared_cache(self)
+    }
+
+    fn observer() -> &'static dyn LoaderObserver {
+        <L as CachedMapLoader<K, V>>::observer()
+    }
 }
 
 /// Just import this trait and use `.load()` or `.load_many()` on any struct
 /// that implements `CachedLoader` or `NonCachedLoader`
 #[async_trait]
 pub trait Loader<K, V, E: ErrBounds, const HAS_CACHE: bool> {
-    async fn load(&self, key: K) -> Result<V, LoaderError<E>>;
+    async fn load(&self, key: K) -> Result<V, LoaderError<K, E>>;
+
+    async fn load_many(&self, keys: Vec<K>) -> Result<HashMap<K, V>, LoaderError<K, E>>;
 
-    async fn load_many(&self, keys: Vec<K>) -> Result<HashMap<K, V>, LoaderError<E>>;
+    /// Same as `load_many`, but values are returned in the order of keys,
+    /// duplicated keys are loaded once and get a copy of the value each
+    async fn load_many_ordered(&self, keys: Vec<K>) -> Result<Vec<V>, LoaderError<K, E>>;
 }
 
 #[async_trait]
@@ -89,19 +361,24 @@ where
     V: CacheVal,
     L: NonCachedLoader<K, V>,
 {
-    async fn load(&self, key: K) -> Result<V, LoaderError<L::Error>> {
+    async fn load(&self, key: K) -> Result<V, LoaderError<K, L::Error>> {
         let mut batch_wrapper = BatchFnWrapper::<_, _, _, _, false>::new(self.clone());
         let loader = InnerLoader::new(&mut batch_wrapper);
         let result = Self::init_loader(loader).try_load(key).await;
         parse_loader_result(result, batch_wrapper.error)
     }
 
-    async fn load_many(&self, keys: Vec<K>) -> Result<HashMap<K, V>, LoaderError<L::Error>> {
+    async fn load_many(&self, keys: Vec<K>) -> Result<HashMap<K, V>, LoaderError<K, L::Error>> {
         let mut batch_wrapper = BatchFnWrapper::<_, _, _, _, false>::new(self.clone());
         let loader = InnerLoader::new(&mut batch_wrapper);
         let result = Self::init_loader(loader).try_load_many(keys).await;
         parse_loader_result(result, batch_wrapper.error)
     }
+
+    async fn load_many_ordered(&self, keys: Vec<K>) -> Result<Vec<V>, LoaderError<K, L::Error>> {
+        let values = self.load_many(keys.clone()).await?;
+        order_values(&keys, values)
+    }
 }
 
 #[async_trait]
@@ -111,7 +388,7 @@ where
     V: CacheVal,
     L: CachedLoader<K, V>,
 {
-    async fn load(&self, key: K) -> Result<V, LoaderError<L::Error>> {
+    async fn load(&self, key: K) -> Result<V, LoaderError<K, L::Error>> {
         let mut batch_wrapper = BatchFnWrapper::<_, _, _, _, true>::new(self.clone());
         let cache = Cacher::get_or_init(Self::init_cache, Self::cache_strategy).await;
         let mut cache_lock = cache.lock().await;
@@ -121,10 +398,16 @@ where
             cache_lock.add_key_to_drop(&key);
         }
         cache_lock.cleanup();
+        observe_cache::<K, V, L>(
+            1,
+            batch_wrapper.loaded_keys,
+            batch_wrapper.shared_hits,
+            cache_lock.take_evictions(),
+        );
         parse_loader_result(result, batch_wrapper.error)
     }
 
-    async fn load_many(&self, keys: Vec<K>) -> Result<HashMap<K, V>, LoaderError<L::Error>> {
+    async fn load_many(&self, keys: Vec<K>) -> Result<HashMap<K, V>, LoaderError<K, L::Error>> {
         let mut batch_wrapper = BatchFnWrapper::<_, _, _, _, true>::new(self.clone());
         let cache = Cacher::get_or_init(Self::init_cache, Self::cache_strategy).await;
         let mut cache_lock = cache.lock().await;
@@ -134,13 +417,116 @@ where
             keys.iter().for_each(|key| cache_lock.add_key_to_drop(key));
         }
         cache_lock.cleanup();
+        let requested = keys.iter().collect::<HashSet<_>>().len();
+        observe_cache::<K, V, L>(
+            requested,
+            batch_wrapper.loaded_keys,
+            batch_wrapper.shared_hits,
+            cache_lock.take_evictions(),
+        );
         parse_loader_result(result, batch_wrapper.error)
     }
+
+    async fn load_many_ordered(&self, keys: Vec<K>) -> Result<Vec<V>, LoaderError<K, L::Error>> {
+        let values = self.load_many(keys.clone()).await?;
+        order_values(&keys, values)
+    }
+}
+
+/// Import this trait to manage the cache of any struct that implements `CachedLoader`
+/// without performing a load, e.g. when an update for some keys arrives
+#[async_trait]
+pub trait CacheControl<K, V> {
+    /// Remove the key from cache (including the shared one),
+    /// so the next load will call `load_fn` for it
+    async fn invalidate(&self, key: &K);
+
+    /// Remove the keys from cache (including the shared one),
+    /// so the next load will call `load_fn` for them
+    async fn invalidate_many(&self, keys: &[K]);
+
+    /// Remove all keys from cache, the shared cache is left untouched
+    async fn clear(&self);
+
+    /// Put the value into cache (including the shared one), if `cache_strategy` allows it
+    async fn prime(&self, key: K, value: V);
+
+    /// Value of the key if it is cached, `load_fn` is never called.
+    /// Only the in-process cache is checked, expired values aren't returned
+    async fn peek(&self, key: &K) -> Option<V>;
+
+    /// Cached values of the keys, keys that aren't cached are skipped
+    async fn peek_many(&self, keys: &[K]) -> HashMap<K, V>;
+
+    /// Whether the key is cached, see `peek`
+    async fn is_cached(&self, key: &K) -> bool;
+}
+
+#[async_trait]
+impl<K, V, L> CacheControl<K, V> for L
+where
+    K: CacheKey,
+    V: CacheVal,
+    L: CachedLoader<K, V>,
+{
+    async fn invalidate(&self, key: &K) {
+        self.invalidate_many(std::slice::from_ref(key)).await
+    }
+
+    async fn invalidate_many(&self, keys: &[K]) {
+        let cache = Cacher::get_or_init(Self::init_cache, Self::cache_strategy).await;
+        cache.lock().await.invalidate(keys);
+        if let Some(shared) = self.shared_cache() {
+            if let Err(e) = shared.remove_many(keys).await {
+                L::observer().on_shared_cache_error(type_name::<L>(), &e);
+            }
+        }
+    }
+
+    async fn clear(&self) {
+        let cache = Cacher::get_or_init(Self::init_cache, Self::cache_strategy).await;
+        cache.lock().await.invalidate_all();
+    }
+
+    async fn prime(&self, key: K, value: V) {
+        if let Some(shared) = self.shared_cache() {
+            if Self::cache_strategy(&key, &value) {
+                let entries = vec![(key.clone(), value.clone())];
+                if let Err(e) = shared.set_many(entries).await {
+                    L::observer().on_shared_cache_error(type_name::<L>(), &e);
+                }
+            }
+        }
+        let cache = Cacher::get_or_init(Self::init_cache, Self::cache_strategy).await;
+        let mut cache_lock = cache.lock().await;
+        cache_lock.prime(key, value);
+        observe_cache::<K, V, L>(0, 0, 0, cache_lock.take_evictions());
+    }
+
+    async fn peek(&self, key: &K) -> Option<V> {
+        let cache = Cacher::get_or_init(Self::init_cache, Self::cache_strategy).await;
+        let mut cache_lock = cache.lock().await;
+        cache_lock.peek(key)
+    }
+
+    async fn peek_many(&self, keys: &[K]) -> HashMap<K, V> {
+        let cache = Cacher::get_or_init(Self::init_cache, Self::cache_strategy).await;
+        let mut cache_lock = cache.lock().await;
+        keys.iter()
+            .filter_map(|k| cache_lock.peek(k).map(|v| (k.clone(), v)))
+            .collect()
+    }
+
+    async fn is_cached(&self, key: &K) -> bool {
+        self.peek(key).await.is_some()
+    }
 }
 
 pub struct BatchFnWrapper<K, V, C, E: ErrBounds, const HAS_CACHE: bool> {
     inner: C,
-    error: Option<LoaderError<E>>,
+    error: Option<LoaderError<K, E>>,
+    loaded_keys: usize,
+    shared_hits: usize,
     _pd: (PhantomData<K>, PhantomData<V>),
 }
 
@@ -149,6 +535,8 @@ impl<K: CacheKey, V: CacheVal, L: NonCachedLoader<K, V>> BatchFnWrapper<K, V, L,
         BatchFnWrapper {
             inner,
             error: None,
+            loaded_keys: 0,
+            shared_hits: 0,
             _pd: (PhantomData, PhantomData),
         }
     }
@@ -159,6 +547,8 @@ impl<K: CacheKey, V: CacheVal, L: CachedLoader<K, V>> BatchFnWrapper<K, V, L, L:
         BatchFnWrapper {
             inner,
             error: None,
+            loaded_keys: 0,
+            shared_hits: 0,
             _pd: (PhantomData, PhantomData),
         }
     }
@@ -169,8 +559,31 @@ impl<K: CacheKey, V: CacheVal, C: NonCachedLoader<K, V>> BatchFn<K, V>
     for &mut BatchFnWrapper<K, V, C, C::Error, false>
 {
     async fn load(&mut self, keys: &[K]) -> HashMap<K, V> {
-        let values = self.inner.load_fn(keys).await;
-        check_values(keys, values).unwrap_or_else(|e| {
+        let started = Instant::now();
+        let inner = &self.inner;
+        let values = call_with_policy(
+            TypeId::of::<C>(),
+            &C::load_policy(),
+            C::is_retryable,
+            || {
+                let mut inner = inner.clone();
+                let keys = keys.to_vec();
+                async move { inner.load_values(&keys).await }
+            },
+        )
+        .await;
+        let values = check_values(keys, values);
+        // `load_fn` isn't called while the circuit is open
+        if !matches!(values, Err(LoaderError::CircuitOpen)) {
+            C::observer().on_batch(
+                type_name::<C>(),
+                keys.len(),
+                started.elapsed(),
+                values.is_ok(),
+            );
+        }
+        self.loaded_keys += keys.len();
+        values.unwrap_or_else(|e| {
             self.error = Some(e);
             HashMap::new()
         })
@@ -182,36 +595,152 @@ impl<K: CacheKey, V: CacheVal, C: CachedLoader<K, V>> BatchFn<K, V>
     for &mut BatchFnWrapper<K, V, C, C::Error, true>
 {
     async fn load(&mut self, keys: &[K]) -> HashMap<K, V> {
-        let values = self.inner.load_fn(keys).await;
-        check_values(keys, values).unwrap_or_else(|e| {
-            self.error = Some(e);
-            HashMap::new()
-        })
+        let observer = C::observer();
+        let loader = type_name::<C>();
+        let shared = self.inner.shared_cache();
+        let mut found = HashMap::new();
+        let mut missing_keys = keys.to_vec();
+        if let Some(shared) = &shared {
+            match shared.get_many(keys).await {
+                Ok(values) => {
+                    missing_keys.clear();
+                    for (key, value) in keys.iter().cloned().zip(values) {
+                        match value {
+                            Some(value) => {
+                                found.insert(key, value);
+                            }
+                            None => missing_keys.push(key),
+                        }
+                    }
+                    observer.on_shared_cache_hits(loader, found.len());
+                    self.shared_hits += found.len();
+                }
+                Err(e) => observer.on_shared_cache_error(loader, &e),
+            }
+        }
+        if missing_keys.is_empty() {
+            return found;
+        }
+
+        let values = load_uncached(&self.inner, &missing_keys).await;
+        self.loaded_keys += missing_keys.len();
+        match values {
+            Ok(values) => {
+                if let Some(shared) = &shared {
+                    let entries = values
+                        .iter()
+                        .filter(|(k, v)| C::cache_strategy(k, v))
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect();
+                    if let Err(e) = shared.set_many(entries).await {
+                        observer.on_shared_cache_error(loader, &e);
+                    }
+                }
+                found.extend(values);
+                found
+            }
+            Err(e) => {
+                self.error = Some(e);
+                HashMap::new()
+            }
+        }
+    }
+}
+
+/// Call `load_fn` of a cached loader with its load policy, bypassing caches
+pub(crate) async fn load_uncached<K: CacheKey, V: CacheVal, L: CachedLoader<K, V>>(
+    loader: &L,
+    keys: &[K],
+) -> Result<HashMap<K, V>, LoaderError<K, L::Error>> {
+    let started = Instant::now();
+    let values = call_with_policy(
+        TypeId::of::<L>(),
+        &L::load_policy(),
+        L::is_retryable,
+        || {
+            let mut loader = loader.clone();
+            let keys = keys.to_vec();
+            async move { loader.load_values(&keys).await }
+        },
+    )
+    .await;
+    let values = check_values(keys, values);
+    // `load_fn` isn't called while the circuit is open
+    if !matches!(values, Err(LoaderError::CircuitOpen)) {
+        L::observer().on_batch(
+            type_name::<L>(),
+            keys.len(),
+            started.elapsed(),
+            values.is_ok(),
+        );
+    }
+    values
+}
+
+fn observe_cache<K: CacheKey, V: CacheVal, L: CachedLoader<K, V>>(
+    requested: usize,
+    loaded: usize,
+    shared_hits: usize,
+    evicted: usize,
+) {
+    let observer = L::observer();
+    let loader = type_name::<L>();
+    // keys found in the shared cache were missing in the in-process one
+    let hits = requested.saturating_sub(loaded + shared_hits);
+    observer.on_cache_hits(loader, hits);
+    observer.on_cache_misses(loader, loaded);
+    if evicted > 0 {
+        observer.on_cache_evictions(loader, evicted);
     }
 }
 
 fn check_values<K: CacheKey, V: CacheVal, E: ErrBounds>(
     keys: &[K],
-    values: Result<Vec<V>, E>,
-) -> Result<HashMap<K, V>, LoaderError<E>> {
-    values.map_err(LoaderError::Other).and_then(|values| {
-        if keys.len() != values.len() {
-            Err(LoaderError::MissingValues(format!(
-                "Keys and values vectors aren't length-equal! keys: {:?} ;;; values: {:?}",
-                &keys, &values
-            )))
-        } else {
-            Ok(keys.iter().cloned().zip(values).collect())
+    values: Result<LoadedValues<K, V>, LoaderError<K, E>>,
+) -> Result<HashMap<K, V>, LoaderError<K, E>> {
+    match values? {
+        LoadedValues::Ordered(values) if keys.len() != values.len() => {
+            Err(LoaderError::LengthMismatch {
+                expected: keys.len(),
+                got: values.len(),
+            })
         }
-    })
+        LoadedValues::Ordered(values) => Ok(keys.iter().cloned().zip(values).collect()),
+        LoadedValues::ByKey(mut values) => {
+            let missing = keys
+                .iter()
+                .filter(|key| !values.contains_key(key))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                return Err(LoaderError::KeysMissing(missing));
+            }
+            Ok(keys.iter().filter_map(|k| values.remove_entry(k)).collect())
+        }
+    }
+}
+
+pub(crate) fn order_values<K: CacheKey, V: CacheVal, E: ErrBounds>(
+    keys: &[K],
+    values: HashMap<K, V>,
+) -> Result<Vec<V>, LoaderError<K, E>> {
+    let missing = keys
+        .iter()
+        .filter(|key| !values.contains_key(key))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(LoaderError::KeysMissing(missing));
+    }
+    Ok(keys.iter().map(|key| values[key].clone()).collect())
 }
 
-fn parse_loader_result<R, E: ErrBounds>(
+fn parse_loader_result<R, K: CacheKey, E: ErrBounds>(
     result: Result<R, std::io::Error>,
-    err: Option<LoaderError<E>>,
-) -> Result<R, LoaderError<E>> {
+    err: Option<LoaderError<K, E>>,
+) -> Result<R, LoaderError<K, E>> {
     match err {
         Some(e) => Err(e),
-        None => result.map_err(|e| LoaderError::MissingValues(e.to_string())),
+        None => result.map_err(|e| LoaderError::Internal(e.to_string())),
     }
 }
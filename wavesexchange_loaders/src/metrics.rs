@@ -0,0 +1,319 @@
+use crate::backend::BackendError;
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+static METRICS: Lazy<LoaderMetrics> = Lazy::new(LoaderMetrics::new);
+
+/// Upper bounds of the `load_fn` batch size histogram
+pub const BATCH_SIZE_BUCKETS: [usize; 9] = [1, 5, 10, 25, 50, 100, 250, 500, 1000];
+
+/// Upper bounds (in seconds) of the `load_fn` latency histogram
+pub const LOAD_DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Metrics collector used by all loaders unless `observer()` is overridden
+pub fn global_metrics() -> &'static LoaderMetrics {
+    &METRICS
+}
+
+/// Receives events from loaders, `loader` is the type name of the loader struct.
+///
+/// All methods are no-op by default, so implement only the ones you need.
+pub trait LoaderObserver: Send + Sync {
+    /// Keys were found in cache, `load_fn` wasn't called for them
+    fn on_cache_hits(&self, _loader: &'static str, _count: usize) {}
+
+    /// Keys weren't found in cache and were passed to `load_fn`
+    fn on_cache_misses(&self, _loader: &'static str, _count: usize) {}
+
+    /// Cache entries were evicted to make room for the new ones
+    fn on_cache_evictions(&self, _loader: &'static str, _count: usize) {}
+
+    /// Keys missing in the in-process cache were found in the shared cache
+    fn on_shared_cache_hits(&self, _loader: &'static str, _count: usize) {}
+
+    /// Shared cache request failed, keys were loaded with `load_fn` instead
+    fn on_shared_cache_error(&self, _loader: &'static str, _error: &BackendError) {}
+
+    /// `load_fn` has been called with `batch_size` keys
+    fn on_batch(&self, _loader: &'static str, _batch_size: usize, _elapsed: Duration, _ok: bool) {}
+}
+
+/// Observer that ignores all events
+pub struct NoopObserver;
+
+impl LoaderObserver for NoopObserver {}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoaderStats {
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub cache_evictions: u64,
+    pub shared_cache_hits: u64,
+    pub shared_cache_errors: u64,
+    /// Number of `load_fn` calls
+    pub batches: u64,
+    /// Total number of keys passed to `load_fn`
+    pub batch_keys: u64,
+    /// Number of failed `load_fn` calls
+    pub errors: u64,
+    /// Non-cumulative counts for `BATCH_SIZE_BUCKETS`
+    pub batch_size_buckets: [u64; BATCH_SIZE_BUCKETS.len()],
+    /// Non-cumulative counts for `LOAD_DURATION_BUCKETS`
+    pub load_duration_buckets: [u64; LOAD_DURATION_BUCKETS.len()],
+    pub load_duration_sum: Duration,
+}
+
+/// Per-loader counters and histograms, exportable in Prometheus text format
+#[derive(Default)]
+pub struct LoaderMetrics {
+    loaders: Mutex<BTreeMap<&'static str, LoaderStats>>,
+}
+
+impl LoaderMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self, loader: &str) -> Option<LoaderStats> {
+        self.loaders.lock().unwrap().get(loader).cloned()
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, LoaderStats> {
+        self.loaders.lock().unwrap().clone()
+    }
+
+    pub fn reset(&self) {
+        self.loaders.lock().unwrap().clear()
+    }
+
+    fn update(&self, loader: &'static str, f: impl FnOnce(&mut LoaderStats)) {
+        f(self.loaders.lock().unwrap().entry(loader).or_default())
+    }
+
+    /// Render all collected metrics in Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let loaders = self.snapshot();
+        let mut out = String::new();
+
+        let counters: [Counter; 7] = [
+            (
+                "loader_cache_hits_total",
+                "Keys served from the loader cache",
+                |s| s.cache_hits,
+            ),
+            (
+                "loader_cache_misses_total",
+                "Keys passed to load_fn because they weren't cached",
+                |s| s.cache_misses,
+            ),
+            (
+                "loader_cache_evictions_total",
+                "Cache entries evicted to make room for the new ones",
+                |s| s.cache_evictions,
+            ),
+            (
+                "loader_shared_cache_hits_total",
+                "Keys served from the shared cache",
+                |s| s.shared_cache_hits,
+            ),
+            (
+                "loader_shared_cache_errors_total",
+                "Failed shared cache requests",
+                |s| s.shared_cache_errors,
+            ),
+            ("loader_batches_total", "Number of load_fn calls", |s| {
+                s.batches
+            }),
+            (
+                "loader_errors_total",
+                "Number of failed load_fn calls",
+                |s| s.errors,
+            ),
+        ];
+
+        for (name, help, value) in counters {
+            write_header(&mut out, name, help, "counter");
+            for (loader, stats) in &loaders {
+                writeln!(
+                    out,
+                    "{}{{loader=\"{}\"}} {}",
+                    name,
+                    escape(loader),
+                    value(stats)
+                )
+                .unwrap();
+            }
+        }
+
+        let name = "loader_batch_size";
+        write_header(
+            &mut out,
+            name,
+            "Number of keys passed to load_fn",
+            "histogram",
+        );
+        for (loader, stats) in &loaders {
+            let bounds = BATCH_SIZE_BUCKETS.iter().map(|b| b.to_string());
+            write_histogram(&mut out, name, loader, bounds, &stats.batch_size_buckets);
+            let sum = stats.batch_keys.to_string();
+            write_histogram_totals(&mut out, name, loader, &sum, stats.batches);
+        }
+
+        let name = "loader_load_duration_seconds";
+        write_header(&mut out, name, "Latency of load_fn calls", "histogram");
+        for (loader, stats) in &loaders {
+            let bounds = LOAD_DURATION_BUCKETS.iter().map(|b| b.to_string());
+            write_histogram(&mut out, name, loader, bounds, &stats.load_duration_buckets);
+            let sum = stats.load_duration_sum.as_secs_f64().to_string();
+            write_histogram_totals(&mut out, name, loader, &sum, stats.batches);
+        }
+
+        out
+    }
+}
+
+impl LoaderObserver for LoaderMetrics {
+    fn on_cache_hits(&self, loader: &'static str, count: usize) {
+        self.update(loader, |s| s.cache_hits += count as u64)
+    }
+
+    fn on_cache_misses(&self, loader: &'static str, count: usize) {
+        self.update(loader, |s| s.cache_misses += count as u64)
+    }
+
+    fn on_cache_evictions(&self, loader: &'static str, count: usize) {
+        self.update(loader, |s| s.cache_evictions += count as u64)
+    }
+
+    fn on_shared_cache_hits(&self, loader: &'static str, count: usize) {
+        self.update(loader, |s| s.shared_cache_hits += count as u64)
+    }
+
+    fn on_shared_cache_error(&self, loader: &'static str, _error: &BackendError) {
+        self.update(loader, |s| s.shared_cache_errors += 1)
+    }
+
+    fn on_batch(&self, loader: &'static str, batch_size: usize, elapsed: Duration, ok: bool) {
+        self.update(loader, |s| {
+            s.batches += 1;
+            s.batch_keys += batch_size as u64;
+            if !ok {
+                s.errors += 1;
+            }
+            if let Some(i) = BATCH_SIZE_BUCKETS.iter().position(|b| batch_size <= *b) {
+                s.batch_size_buckets[i] += 1;
+            }
+            let secs = elapsed.as_secs_f64();
+            if let Some(i) = LOAD_DURATION_BUCKETS.iter().position(|b| secs <= *b) {
+                s.load_duration_buckets[i] += 1;
+            }
+            s.load_duration_sum += elapsed;
+        })
+    }
+}
+
+type Counter = (&'static str, &'static str, fn(&LoaderStats) -> u64);
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn write_histogram(
+    out: &mut String,
+    name: &str,
+    loader: &str,
+    bounds: impl Iterator<Item = String>,
+    buckets: &[u64],
+) {
+    let mut cumulative = 0;
+    for (bound, count) in bounds.zip(buckets) {
+        cumulative += count;
+        writeln!(
+            out,
+            "{}_bucket{{loader=\"{}\",le=\"{}\"}} {}",
+            name,
+            escape(loader),
+            bound,
+            cumulative
+        )
+        .unwrap();
+    }
+}
+
+fn write_histogram_totals(out: &mut String, name: &str, loader: &str, sum: &str, count: u64) {
+    let loader = escape(loader);
+    writeln!(
+        out,
+        "{}_bucket{{loader=\"{}\",le=\"+Inf\"}} {}",
+        name, loader, count
+    )
+    .unwrap();
+    writeln!(out, "{}_sum{{loader=\"{}\"}} {}", name, loader, sum).unwrap();
+    writeln!(out, "{}_count{{loader=\"{}\"}} {}", name, loader, count).unwrap();
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_collection() {
+        let metrics = LoaderMetrics::new();
+        metrics.on_cache_hits("a", 3);
+        metrics.on_cache_misses("a", 2);
+        metrics.on_batch("a", 2, Duration::from_millis(20), true);
+        metrics.on_batch("a", 30, Duration::from_secs(20), false);
+
+        let stats = metrics.stats("a").unwrap();
+        assert_eq!(stats.cache_hits, 3);
+        assert_eq!(stats.cache_misses, 2);
+        assert_eq!(stats.batches, 2);
+        assert_eq!(stats.batch_keys, 32);
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.batch_size_buckets[1], 1);
+        assert_eq!(stats.batch_size_buckets[4], 1);
+        assert_eq!(stats.load_duration_buckets[2], 1);
+        assert_eq!(stats.load_duration_buckets.iter().sum::<u64>(), 1);
+        assert!(metrics.stats("b").is_none());
+    }
+
+    #[test]
+    fn test_prometheus_format() {
+        let metrics = LoaderMetrics::new();
+        metrics.on_cache_hits("my::\"Loader\"", 1);
+        metrics.on_batch("my::\"Loader\"", 7, Duration::from_millis(3), true);
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("# TYPE loader_cache_hits_total counter\n"));
+        assert!(text.contains("loader_cache_hits_total{loader=\"my::\\\"Loader\\\"\"} 1\n"));
+        assert!(text.contains("loader_errors_total{loader=\"my::\\\"Loader\\\"\"} 0\n"));
+        assert!(
+            text.contains("loader_batch_size_bucket{loader=\"my::\\\"Loader\\\"\",le=\"5\"} 0\n")
+        );
+        assert!(
+            text.contains("loader_batch_size_bucket{loader=\"my::\\\"Loader\\\"\",le=\"10\"} 1\n")
+        );
+        assert!(text
+            .contains("loader_batch_size_bucket{loader=\"my::\\\"Loader\\\"\",le=\"+Inf\"} 1\n"));
+        assert!(text.contains("loader_batch_size_sum{loader=\"my::\\\"Loader\\\"\"} 7\n"));
+        assert!(text.contains(
+            "loader_load_duration_seconds_bucket{loader=\"my::\\\"Loader\\\"\",le=\"0.005\"} 1\n"
+        ));
+        assert!(
+            text.contains("loader_load_duration_seconds_count{loader=\"my::\\\"Loader\\\"\"} 1\n")
+        );
+    }
+}
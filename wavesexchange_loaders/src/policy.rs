@@ -0,0 +1,355 @@
+use crate::cacher::ErrBounds;
+use crate::error::LoaderError;
+use once_cell::sync::Lazy;
+use std::any::TypeId;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+static BREAKERS: Lazy<Mutex<HashMap<TypeId, Arc<CircuitBreaker>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// How `load_fn` is called: per-batch timeout, retries and circuit breaking.
+///
+/// Everything is disabled by default, override `load_policy()` of a loader to enable it:
+/// ```ignore
+/// fn load_policy() -> LoadPolicy {
+///     LoadPolicy {
+///         timeout: Some(Duration::from_secs(5)),
+///         retry: Some(RetryPolicy::exponential(3, Duration::from_millis(100))),
+///         circuit_breaker: Some(CircuitBreakerConfig::default()),
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoadPolicy {
+    /// Max duration of a single `load_fn` call, `LoaderError::Timeout` is returned after it
+    pub timeout: Option<Duration>,
+    /// Retry failed `load_fn` calls, see `is_retryable()` of a loader
+    pub retry: Option<RetryPolicy>,
+    /// Fail fast with `LoaderError::CircuitOpen` after repeated failures
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+/// Exponential backoff between `load_fn` retries
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first failed call
+    pub max_retries: u32,
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay
+    pub max_backoff: Duration,
+    /// Delay is multiplied by it after each retry
+    pub multiplier: f64,
+    /// Share of the delay that is randomized, from 0.0 (fixed delay) to 1.0 (full jitter)
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Doubling backoff capped at 10 seconds, with 50% jitter
+    pub fn exponential(max_retries: u32, initial_backoff: Duration) -> Self {
+        RetryPolicy {
+            max_retries,
+            initial_backoff,
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.5,
+        }
+    }
+
+    /// Delay before the retry number `retry` (starting from 0), without jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1.0)
+            .powi(retry.min(i32::MAX as u32) as i32);
+        let delay = self.initial_backoff.as_secs_f64() * factor;
+        Duration::from_secs_f64(delay.min(self.max_backoff.as_secs_f64()))
+    }
+
+    fn jittered_backoff(&self, retry: u32) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        self.backoff(retry)
+            .mul_f64(1.0 - jitter * random_fraction())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failed batches after which the circuit opens
+    pub failure_threshold: u32,
+    /// Time the circuit stays open; after it a single trial batch is let through
+    /// while other batches still fail with `CircuitOpen`,
+    /// and the circuit is closed if it succeeds or opened again otherwise
+    pub reset_timeout: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            reset_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum CircuitState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen,
+}
+
+/// Circuit breaker state of a single loader type
+struct CircuitBreaker {
+    state: Mutex<CircuitState>,
+}
+
+impl CircuitBreaker {
+    fn new() -> Self {
+        CircuitBreaker {
+            state: Mutex::new(CircuitState::Closed { failures: 0 }),
+        }
+    }
+
+    fn get(loader: TypeId) -> Arc<CircuitBreaker> {
+        let mut breakers = BREAKERS.lock().unwrap();
+        breakers
+            .entry(loader)
+            .or_insert_with(|| Arc::new(CircuitBreaker::new()))
+            .clone()
+    }
+
+    /// `None` if the call is rejected, `Some(true)` if it's the trial call of a half-open circuit
+    fn allows_call(&self, now: Instant) -> Option<bool> {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            CircuitState::Closed { .. } => Some(false),
+            CircuitState::Open { until } if now >= until => {
+                *state = CircuitState::HalfOpen;
+                Some(true)
+            }
+            // only a single trial call is let through until it resolves
+            _ => None,
+        }
+    }
+
+    /// Let the next call try again if the trial call was cancelled before resolving
+    fn on_cancel(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        if *state == CircuitState::HalfOpen {
+            *state = CircuitState::Open { until: now };
+        }
+    }
+
+    fn on_success(&self) {
+        *self.state.lock().unwrap() = CircuitState::Closed { failures: 0 };
+    }
+
+    fn on_failure(&self, config: &CircuitBreakerConfig, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            CircuitState::Closed { failures } => failures + 1,
+            _ => config.failure_threshold,
+        };
+        *state = if failures >= config.failure_threshold {
+            CircuitState::Open {
+                until: now + config.reset_timeout,
+            }
+        } else {
+            CircuitState::Closed { failures }
+        };
+    }
+}
+
+/// Call `load_fn` (wrapped into `attempt`) according to the policy of the loader `loader`.
+///
+/// Only timeouts and errors accepted by `is_retryable` are retried and counted
+/// as circuit breaker failures.
+pub(crate) async fn call_with_policy<K, T, E, F, Fut>(
+    loader: TypeId,
+    policy: &LoadPolicy,
+    is_retryable: fn(&E) -> bool,
+    mut attempt: F,
+) -> Result<T, LoaderError<K, E>>
+where
+    K: Debug,
+    E: ErrBounds,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let breaker = policy
+        .circuit_breaker
+        .as_ref()
+        .map(|config| (config, CircuitBreaker::get(loader)));
+    let _trial = match &breaker {
+        Some((_, breaker)) => match breaker.allows_call(Instant::now()) {
+            Some(trial) => trial.then(|| TrialGuard(breaker.clone())),
+            None => return Err(LoaderError::CircuitOpen),
+        },
+        None => None,
+    };
+
+    let max_retries = policy.retry.as_ref().map_or(0, |r| r.max_retries);
+    let mut retry = 0;
+    let result = loop {
+        let result = match policy.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, attempt()).await {
+                Ok(result) => result.map_err(LoaderError::Other),
+                Err(_) => Err(LoaderError::Timeout(timeout)),
+            },
+            None => attempt().await.map_err(LoaderError::Other),
+        };
+        let retryable = match &result {
+            Ok(_) => false,
+            Err(LoaderError::Other(e)) => is_retryable(e),
+            Err(_) => true,
+        };
+        if !retryable || retry >= max_retries {
+            break result;
+        }
+        if let Some(retry_policy) = &policy.retry {
+            tokio::time::sleep(retry_policy.jittered_backoff(retry)).await;
+        }
+        retry += 1;
+    };
+
+    if let Some((config, breaker)) = &breaker {
+        match &result {
+            Err(LoaderError::Other(e)) if is_retryable(e) => {
+                breaker.on_failure(config, Instant::now())
+            }
+            Err(LoaderError::Timeout(_)) => breaker.on_failure(config, Instant::now()),
+            Ok(_) => breaker.on_success(),
+            // non-retryable errors say nothing about the health of the source,
+            // an unresolved trial call lets the next one try again (see `TrialGuard`)
+            Err(_) => {}
+        }
+    }
+    result
+}
+
+// reopens the circuit if the trial call is dropped or ends without resolving the circuit
+struct TrialGuard(Arc<CircuitBreaker>);
+
+impl Drop for TrialGuard {
+    fn drop(&mut self) {
+        self.0.on_cancel(Instant::now())
+    }
+}
+
+/// Random number in `[0, 1)`, good enough for jitter
+fn random_fraction() -> f64 {
+    // every `RandomState` is seeded with different keys
+    let hash = RandomState::new().build_hasher().finish();
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_backoff: Duration::from_millis(500),
+            ..RetryPolicy::exponential(5, Duration::from_millis(100))
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(3), Duration::from_millis(500));
+
+        for retry in 0..5 {
+            let delay = policy.jittered_backoff(retry);
+            assert!(delay <= policy.backoff(retry));
+            assert!(delay >= policy.backoff(retry) / 2);
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 2,
+            reset_timeout: Duration::from_secs(10),
+        };
+        let breaker = CircuitBreaker::new();
+        let now = Instant::now();
+
+        breaker.on_failure(&config, now);
+        assert_eq!(breaker.allows_call(now), Some(false));
+        breaker.on_success();
+        breaker.on_failure(&config, now);
+        assert_eq!(breaker.allows_call(now), Some(false));
+        breaker.on_failure(&config, now);
+        assert_eq!(breaker.allows_call(now), None);
+
+        //trial call fails and the circuit is opened again
+        let later = now + config.reset_timeout;
+        assert_eq!(breaker.allows_call(later), Some(true));
+        assert_eq!(*breaker.state.lock().unwrap(), CircuitState::HalfOpen);
+        assert_eq!(breaker.allows_call(later), None);
+        breaker.on_failure(&config, later);
+        assert_eq!(breaker.allows_call(later), None);
+
+        //trial call is cancelled, so the next call is a trial one and succeeds
+        let later = later + config.reset_timeout;
+        assert_eq!(breaker.allows_call(later), Some(true));
+        breaker.on_cancel(later);
+        assert_eq!(breaker.allows_call(later), Some(true));
+        breaker.on_success();
+        assert_eq!(
+            *breaker.state.lock().unwrap(),
+            CircuitState::Closed { failures: 0 }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_breaker_of_regular_calls() {
+        struct Loader;
+
+        let policy = LoadPolicy {
+            circuit_breaker: Some(CircuitBreakerConfig::default()),
+            ..LoadPolicy::default()
+        };
+        let loader = TypeId::of::<Loader>();
+        let breaker = CircuitBreaker::get(loader);
+        let set_state = |state| *breaker.state.lock().unwrap() = state;
+
+        //regular call is cancelled while another one is the trial call
+        let call = call_with_policy::<(), _, &str, _, _>(
+            loader,
+            &policy,
+            |_| true,
+            || async {
+                set_state(CircuitState::HalfOpen);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok(())
+            },
+        );
+        assert!(tokio::time::timeout(Duration::from_millis(10), call)
+            .await
+            .is_err());
+        assert_eq!(*breaker.state.lock().unwrap(), CircuitState::HalfOpen);
+
+        //non-retryable errors leave the state unchanged
+        set_state(CircuitState::Closed { failures: 1 });
+        let result = call_with_policy::<(), (), _, _, _>(
+            loader,
+            &policy,
+            |_| false,
+            || async { Err("bad request") },
+        )
+        .await;
+        assert_eq!(result, Err(LoaderError::Other("bad request")));
+        assert_eq!(
+            *breaker.state.lock().unwrap(),
+            CircuitState::Closed { failures: 1 }
+        );
+    }
+}
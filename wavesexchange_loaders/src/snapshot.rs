@@ -0,0 +1,371 @@
+use crate::cacher::{CacheKey, CacheVal, Cacher};
+use crate::loaders::CachedLoader;
+use crate::{SizedCache, TimedCache, TimedSizedCache, UnboundCache};
+use cached::Cached;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::any::type_name;
+use std::hash::Hash;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// version of the file layout itself, bumped on incompatible changes of `SnapshotFile`
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SnapshotError {
+    #[error("Snapshot IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Snapshot serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Snapshot version {found} doesn't match the expected {expected}")]
+    VersionMismatch { expected: u32, found: u32 },
+    #[error("Snapshot file format {found} isn't supported, expected {expected}")]
+    FormatMismatch { expected: u32, found: u32 },
+    #[error("Snapshot of {found} values can't be restored into a cache of {expected}")]
+    TypeMismatch { expected: String, found: String },
+}
+
+/// Cache which entries can be listed, needed to snapshot it
+pub trait CacheEntries<K, V> {
+    /// Cached entries with their remaining lifetime, `None` for entries that don't expire
+    fn entries(&self) -> Vec<(K, V, Option<Duration>)>;
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> CacheEntries<K, V> for UnboundCache<K, V> {
+    fn entries(&self) -> Vec<(K, V, Option<Duration>)> {
+        self.get_store()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone(), None))
+            .collect()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> CacheEntries<K, V> for SizedCache<K, V> {
+    fn entries(&self) -> Vec<(K, V, Option<Duration>)> {
+        self.key_order()
+            .zip(self.value_order())
+            .map(|(k, v)| (k.clone(), v.clone(), None))
+            .collect()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> CacheEntries<K, V> for TimedCache<K, V> {
+    fn entries(&self) -> Vec<(K, V, Option<Duration>)> {
+        let lifespan = Duration::from_secs(self.cache_lifespan().unwrap_or_default());
+        self.get_store()
+            .iter()
+            .filter_map(|(k, (inserted_at, v))| {
+                let ttl = lifespan.checked_sub(inserted_at.elapsed())?;
+                Some((k.clone(), v.clone(), Some(ttl)))
+            })
+            .collect()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> CacheEntries<K, V> for TimedSizedCache<K, V> {
+    fn entries(&self) -> Vec<(K, V, Option<Duration>)> {
+        let lifespan = Duration::from_secs(self.cache_lifespan().unwrap_or_default());
+        self.key_order()
+            .zip(self.value_order())
+            .filter_map(|(k, (inserted_at, v))| {
+                let ttl = lifespan.checked_sub(inserted_at.elapsed())?;
+                Some((k.clone(), v.clone(), Some(ttl)))
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile<E> {
+    format: u32,
+    version: u32,
+    value_type: String,
+    saved_at_ms: u64,
+    entries: E,
+}
+
+// key, value and the remaining lifetime in ms
+type SnapshotEntry<K, V> = (K, V, Option<u64>);
+
+/// Saving the cache of a `CachedLoader` to a file and restoring it, e.g. to start warm after a deploy.
+///
+/// ```ignore
+/// // on shutdown
+/// AssetsLoader::new().save_snapshot(Path::new("/data/assets.json"), 1).await?;
+/// // on startup
+/// if let Err(e) = AssetsLoader::new().restore_snapshot(Path::new("/data/assets.json"), 1).await {
+///     warn!("cache is not restored: {}", e);
+/// }
+/// ```
+///
+/// Entries are saved as JSON with their remaining lifetime, time passed between saving
+/// and restoring is subtracted from it, and expired entries are skipped.
+/// Only the in-process cache is saved.
+///
+/// A snapshot is restored only if its `version` matches, so bump the version when
+/// the serialized form of keys or values changes. Snapshots of other value types,
+/// or ones that can't be deserialized, are rejected as well, leaving the cache untouched.
+///
+/// Enabled with the `snapshot` feature.
+#[async_trait]
+pub trait CacheSnapshot<K, V> {
+    /// Save cached entries to `path`, returns the number of saved entries
+    async fn save_snapshot(&self, path: &Path, version: u32) -> Result<usize, SnapshotError>;
+
+    /// Put entries saved to `path` into cache, returns the number of restored entries
+    async fn restore_snapshot(&self, path: &Path, version: u32) -> Result<usize, SnapshotError>;
+}
+
+#[async_trait]
+impl<K, V, L> CacheSnapshot<K, V> for L
+where
+    K: CacheKey + Serialize + DeserializeOwned,
+    V: CacheVal + Serialize + DeserializeOwned,
+    L: CachedLoader<K, V>,
+    L::Cache: CacheEntries<K, V>,
+{
+    async fn save_snapshot(&self, path: &Path, version: u32) -> Result<usize, SnapshotError> {
+        let cache = Cacher::get_or_init(Self::init_cache, Self::cache_strategy).await;
+        let entries = cache
+            .lock()
+            .await
+            .entries()
+            .into_iter()
+            .map(|(k, v, ttl)| (k, v, ttl.map(|ttl| ttl.as_millis() as u64)))
+            .collect::<Vec<SnapshotEntry<K, V>>>();
+        let saved = entries.len();
+        let snapshot = SnapshotFile {
+            format: FORMAT_VERSION,
+            version,
+            value_type: type_name::<V>().to_string(),
+            saved_at_ms: unix_time_ms(),
+            entries,
+        };
+        let data = serde_json::to_vec(&snapshot)?;
+        // a partially written file must not replace the previous snapshot,
+        // the whole file name is kept so that the temp file doesn't collide with other ones
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        tokio::fs::write(&tmp_path, data).await?;
+        tokio::fs::rename(&tmp_path, path).await?;
+        Ok(saved)
+    }
+
+    async fn restore_snapshot(&self, path: &Path, version: u32) -> Result<usize, SnapshotError> {
+        let data = tokio::fs::read(path).await?;
+        let snapshot: SnapshotFile<serde_json::Value> = serde_json::from_slice(&data)?;
+        if snapshot.format != FORMAT_VERSION {
+            return Err(SnapshotError::FormatMismatch {
+                expected: FORMAT_VERSION,
+                found: snapshot.format,
+            });
+        }
+        if snapshot.version != version {
+            return Err(SnapshotError::VersionMismatch {
+                expected: version,
+                found: snapshot.version,
+            });
+        }
+        if snapshot.value_type != type_name::<V>() {
+            return Err(SnapshotError::TypeMismatch {
+                expected: type_name::<V>().to_string(),
+                found: snapshot.value_type,
+            });
+        }
+        let entries: Vec<SnapshotEntry<K, V>> = serde_json::from_value(snapshot.entries)?;
+        let passed = unix_time_ms().saturating_sub(snapshot.saved_at_ms);
+
+        let cache = Cacher::get_or_init(Self::init_cache, Self::cache_strategy).await;
+        let mut cache_lock = cache.lock().await;
+        let mut restored = 0;
+        for (k, v, ttl) in entries {
+            let ttl = match ttl {
+                Some(ttl) if ttl <= passed => continue,
+                Some(ttl) => Some(Duration::from_millis(ttl - passed)),
+                None => None,
+            };
+            if cache_lock.restore(k, v, ttl) {
+                restored += 1;
+            }
+        }
+        Ok(restored)
+    }
+}
+
+fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_batches, assert_cached, Recorder};
+    use crate::{CacheControl, Loader};
+    use std::path::PathBuf;
+
+    #[derive(Clone)]
+    struct Balances {
+        recorder: Recorder<u16>,
+    }
+
+    #[async_trait]
+    impl CachedLoader<u16, String> for Balances {
+        type Cache = TimedSizedCache<u16, String>;
+        type Error = ();
+
+        async fn load_fn(&mut self, keys: &[u16]) -> Result<Vec<String>, ()> {
+            self.recorder.record(keys);
+            Ok(keys.iter().map(|k| k.to_string()).collect())
+        }
+
+        fn init_cache() -> Self::Cache {
+            TimedSizedCache::with_size_and_lifespan(10, 60)
+        }
+    }
+
+    // same keys, other values
+    #[derive(Clone)]
+    struct Flags;
+
+    #[async_trait]
+    impl CachedLoader<u16, bool> for Flags {
+        type Cache = UnboundCache<u16, bool>;
+        type Error = ();
+
+        async fn load_fn(&mut self, keys: &[u16]) -> Result<Vec<bool>, ()> {
+            Ok(keys.iter().map(|k| k % 2 == 0).collect())
+        }
+
+        fn init_cache() -> Self::Cache {
+            UnboundCache::new()
+        }
+    }
+
+    // caches only enabled flags
+    #[derive(Clone)]
+    struct Enabled;
+
+    #[async_trait]
+    impl CachedLoader<u32, bool> for Enabled {
+        type Cache = UnboundCache<u32, bool>;
+        type Error = ();
+
+        async fn load_fn(&mut self, keys: &[u32]) -> Result<Vec<bool>, ()> {
+            Ok(keys.iter().map(|k| k % 2 == 0).collect())
+        }
+
+        fn init_cache() -> Self::Cache {
+            UnboundCache::new()
+        }
+
+        fn cache_strategy(_: &u32, enabled: &bool) -> bool {
+            *enabled
+        }
+    }
+
+    fn snapshot_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("loaders_{}_{}.json", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let path = snapshot_path("balances");
+        let loader = Balances {
+            recorder: Recorder::new(),
+        };
+        loader.load_many(vec![1, 2]).await.unwrap();
+        //files with the same stem are left untouched
+        let other_path = path.with_extension("tmp");
+        std::fs::write(&other_path, "other").unwrap();
+        assert_eq!(loader.save_snapshot(&path, 1).await.unwrap(), 2);
+        assert_eq!(std::fs::read_to_string(&other_path).unwrap(), "other");
+        std::fs::remove_file(other_path).unwrap();
+
+        //as if the service was restarted
+        loader.clear().await;
+        assert_eq!(loader.restore_snapshot(&path, 1).await.unwrap(), 2);
+        let value = assert_cached(&loader.recorder, loader.load(2)).await;
+        assert_eq!(value, Ok("2".to_string()));
+
+        //incompatible snapshots are rejected
+        loader.clear().await;
+        assert!(matches!(
+            loader.restore_snapshot(&path, 2).await,
+            Err(SnapshotError::VersionMismatch {
+                expected: 2,
+                found: 1
+            })
+        ));
+        assert!(matches!(
+            Flags.restore_snapshot(&path, 1).await,
+            Err(SnapshotError::TypeMismatch { .. })
+        ));
+        assert!(!Flags.is_cached(&1).await);
+        let value = assert_batches(&loader.recorder, vec![vec![1]], loader.load(1)).await;
+        assert_eq!(value, Ok("1".to_string()));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_ttl() {
+        let path = snapshot_path("flags");
+        let snapshot = SnapshotFile {
+            format: FORMAT_VERSION,
+            version: 0,
+            value_type: type_name::<bool>().to_string(),
+            saved_at_ms: unix_time_ms() - 10_000,
+            entries: vec![
+                (1, true, Some(5_000)),
+                (2, false, Some(60_000)),
+                (3, true, None),
+            ],
+        };
+        std::fs::write(&path, serde_json::to_vec(&snapshot).unwrap()).unwrap();
+
+        //the first entry expired while the service was down
+        assert_eq!(Flags.restore_snapshot(&path, 0).await.unwrap(), 2);
+        assert_eq!(Flags.peek_many(&[1, 2, 3]).await.len(), 2);
+        let entries = Cacher::get_or_init(Flags::init_cache, Flags::cache_strategy)
+            .await
+            .lock()
+            .await
+            .entries();
+        let ttl = entries.iter().find(|(k, _, _)| *k == 2).unwrap().2.unwrap();
+        assert!(ttl <= Duration::from_secs(50));
+        assert!(entries.iter().any(|(k, _, ttl)| *k == 3 && ttl.is_none()));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_restored_entries() {
+        let path = snapshot_path("enabled");
+        let mut snapshot = SnapshotFile {
+            format: FORMAT_VERSION + 1,
+            version: 1,
+            value_type: type_name::<bool>().to_string(),
+            saved_at_ms: unix_time_ms(),
+            entries: vec![(1, false, None::<u64>), (2, true, None)],
+        };
+        std::fs::write(&path, serde_json::to_vec(&snapshot).unwrap()).unwrap();
+        assert!(matches!(
+            Enabled.restore_snapshot(&path, 1).await,
+            Err(SnapshotError::FormatMismatch { expected, found })
+                if expected == FORMAT_VERSION && found == FORMAT_VERSION + 1
+        ));
+
+        //entries rejected by `cache_strategy` aren't counted
+        snapshot.format = FORMAT_VERSION;
+        std::fs::write(&path, serde_json::to_vec(&snapshot).unwrap()).unwrap();
+        assert_eq!(Enabled.restore_snapshot(&path, 1).await.unwrap(), 1);
+        assert!(!Enabled.is_cached(&1).await);
+        assert!(Enabled.is_cached(&2).await);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,341 @@
+//! Utilities for testing loaders without relying on wall-clock timing.
+//!
+//! ```ignore
+//! #[derive(Clone)]
+//! struct Assets {
+//!     recorder: Recorder<String>,
+//! }
+//!
+//! #[async_trait]
+//! impl CachedLoader<String, Asset> for Assets {
+//!     type Cache = MockTimedCache<String, Asset>;
+//!
+//!     async fn load_fn(&mut self, keys: &[String]) -> Result<Vec<Asset>, Self::Error> {
+//!         self.recorder.record(keys);
+//!         ...
+//!     }
+//!
+//!     fn init_cache() -> Self::Cache {
+//!         MockTimedCache::with_lifespan(&CLOCK, 60)
+//!     }
+//! }
+//!
+//! let assets = Assets { recorder: Recorder::new() };
+//! assert_batches(&assets.recorder, vec![vec![a.clone(), b]], assets.load_many(keys)).await;
+//! assert_cached(&assets.recorder, assets.load(a.clone())).await;
+//! CLOCK.advance(Duration::from_secs(60));
+//! assert_batches(&assets.recorder, vec![vec![a.clone()]], assets.load(a)).await;
+//! ```
+//!
+//! Enabled with the `testing` feature.
+
+use crate::cacher::{CacheBounds, CacheKey, CacheVal, ErrBounds};
+use crate::loaders::NonCachedMapLoader;
+use cached::Cached;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Records keys passed to `load_fn`, put it into a loader and call `record` in `load_fn`.
+///
+/// Clones share the records.
+#[derive(Debug)]
+pub struct Recorder<K> {
+    batches: Arc<Mutex<Vec<Vec<K>>>>,
+}
+
+impl<K> Clone for Recorder<K> {
+    fn clone(&self) -> Self {
+        Recorder {
+            batches: self.batches.clone(),
+        }
+    }
+}
+
+impl<K> Default for Recorder<K> {
+    fn default() -> Self {
+        Recorder {
+            batches: Arc::default(),
+        }
+    }
+}
+
+impl<K: Clone> Recorder<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, keys: &[K]) {
+        self.batches.lock().unwrap().push(keys.to_vec());
+    }
+
+    /// Number of `load_fn` calls
+    pub fn calls(&self) -> usize {
+        self.batches.lock().unwrap().len()
+    }
+
+    /// Keys of every `load_fn` call
+    pub fn batches(&self) -> Vec<Vec<K>> {
+        self.batches.lock().unwrap().clone()
+    }
+
+    /// All loaded keys
+    pub fn keys(&self) -> Vec<K> {
+        self.batches().concat()
+    }
+
+    /// Batches recorded since the last call
+    pub fn take(&self) -> Vec<Vec<K>> {
+        std::mem::take(&mut *self.batches.lock().unwrap())
+    }
+}
+
+type MockFn<K, V, E> = Arc<dyn Fn(&K) -> Result<V, E> + Send + Sync>;
+
+/// Non-cached loader computing values with a function and recording its calls.
+///
+/// A batch fails with the first error returned by the function.
+pub struct MockLoader<K, V, E = ()> {
+    recorder: Recorder<K>,
+    f: MockFn<K, V, E>,
+}
+
+impl<K, V, E> Clone for MockLoader<K, V, E> {
+    fn clone(&self) -> Self {
+        MockLoader {
+            recorder: self.recorder.clone(),
+            f: self.f.clone(),
+        }
+    }
+}
+
+impl<K: Clone, V, E> MockLoader<K, V, E> {
+    pub fn new(f: impl Fn(&K) -> Result<V, E> + Send + Sync + 'static) -> Self {
+        MockLoader {
+            recorder: Recorder::new(),
+            f: Arc::new(f),
+        }
+    }
+
+    pub fn recorder(&self) -> &Recorder<K> {
+        &self.recorder
+    }
+}
+
+#[async_trait]
+impl<K, V, E> NonCachedMapLoader<K, V> for MockLoader<K, V, E>
+where
+    K: CacheKey,
+    V: CacheVal,
+    E: ErrBounds + 'static,
+{
+    type Error = E;
+
+    async fn load_fn_map(&mut self, keys: &[K]) -> Result<HashMap<K, V>, E> {
+        self.recorder.record(keys);
+        keys.iter().map(|k| Ok((k.clone(), (self.f)(k)?))).collect()
+    }
+}
+
+/// Clock that moves only when told to, clones share the time
+#[derive(Clone, Debug, Default)]
+pub struct MockClock {
+    elapsed_nanos: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    /// Time elapsed since the clock was created
+    pub fn now(&self) -> Duration {
+        Duration::from_nanos(self.elapsed_nanos.load(Ordering::SeqCst))
+    }
+}
+
+/// Replacement of `TimedCache` in tests, values expire when `MockClock` is advanced past their lifespan
+pub struct MockTimedCache<K, V> {
+    clock: MockClock,
+    lifespan: Duration,
+    store: HashMap<K, (Duration, V)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl<K: Hash + Eq, V> MockTimedCache<K, V> {
+    pub fn with_lifespan(clock: &MockClock, seconds: u64) -> Self {
+        MockTimedCache {
+            clock: clock.clone(),
+            lifespan: Duration::from_secs(seconds),
+            store: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn is_expired(&self, inserted_at: Duration) -> bool {
+        self.clock.now() - inserted_at >= self.lifespan
+    }
+}
+
+impl<K: CacheKey, V: CacheVal> CacheBounds<K, V> for MockTimedCache<K, V> {}
+
+impl<K: Hash + Eq, V> Cached<K, V> for MockTimedCache<K, V> {
+    fn cache_get(&mut self, k: &K) -> Option<&V> {
+        self.cache_get_mut(k).map(|v| &*v)
+    }
+
+    fn cache_get_mut(&mut self, k: &K) -> Option<&mut V> {
+        let expired = match self.store.get(k) {
+            Some((inserted_at, _)) => self.is_expired(*inserted_at),
+            None => {
+                self.misses += 1;
+                return None;
+            }
+        };
+        if expired {
+            self.store.remove(k);
+            self.misses += 1;
+            return None;
+        }
+        self.hits += 1;
+        self.store.get_mut(k).map(|(_, v)| v)
+    }
+
+    fn cache_set(&mut self, k: K, v: V) -> Option<V> {
+        let now = self.clock.now();
+        self.store.insert(k, (now, v)).map(|(_, v)| v)
+    }
+
+    fn cache_get_or_set_with<F: FnOnce() -> V>(&mut self, k: K, f: F) -> &mut V {
+        let now = self.clock.now();
+        if matches!(self.store.get(&k), Some((inserted_at, _)) if self.is_expired(*inserted_at)) {
+            self.store.remove(&k);
+        }
+        &mut self.store.entry(k).or_insert_with(|| (now, f())).1
+    }
+
+    fn cache_remove(&mut self, k: &K) -> Option<V> {
+        self.store.remove(k).map(|(_, v)| v)
+    }
+
+    fn cache_clear(&mut self) {
+        self.store.clear()
+    }
+
+    fn cache_reset(&mut self) {
+        self.store = HashMap::new()
+    }
+
+    fn cache_reset_metrics(&mut self) {
+        self.hits = 0;
+        self.misses = 0;
+    }
+
+    fn cache_size(&self) -> usize {
+        self.store.len()
+    }
+
+    fn cache_hits(&self) -> Option<u64> {
+        Some(self.hits)
+    }
+
+    fn cache_misses(&self) -> Option<u64> {
+        Some(self.misses)
+    }
+
+    fn cache_lifespan(&self) -> Option<u64> {
+        Some(self.lifespan.as_secs())
+    }
+}
+
+#[cfg(feature = "snapshot")]
+impl<K: Hash + Eq + Clone, V: Clone> crate::snapshot::CacheEntries<K, V> for MockTimedCache<K, V> {
+    fn entries(&self) -> Vec<(K, V, Option<Duration>)> {
+        let now = self.clock.now();
+        self.store
+            .iter()
+            .filter_map(|(k, (inserted_at, v))| {
+                let ttl = self.lifespan.checked_sub(now - *inserted_at)?;
+                Some((k.clone(), v.clone(), Some(ttl)))
+            })
+            .collect()
+    }
+}
+
+/// Await `fut`, asserting that it doesn't call `load_fn`
+pub async fn assert_cached<K: Clone + Debug, T>(
+    recorder: &Recorder<K>,
+    fut: impl Future<Output = T>,
+) -> T {
+    let before = recorder.calls();
+    let result = fut.await;
+    let batches = recorder.batches();
+    assert!(
+        batches.len() == before,
+        "expected no load_fn calls, got {:?}",
+        &batches[before..]
+    );
+    result
+}
+
+/// Await `fut`, asserting that it calls `load_fn` with exactly these batches of keys.
+///
+/// The order of keys within a batch doesn't matter.
+pub async fn assert_batches<K: Clone + Debug + Ord, T>(
+    recorder: &Recorder<K>,
+    mut expected: Vec<Vec<K>>,
+    fut: impl Future<Output = T>,
+) -> T {
+    let before = recorder.calls();
+    let result = fut.await;
+    let mut batches = recorder.batches().split_off(before);
+    batches.iter_mut().for_each(|batch| batch.sort());
+    expected.iter_mut().for_each(|batch| batch.sort());
+    assert_eq!(batches, expected, "unexpected load_fn calls");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Loader;
+
+    #[tokio::test]
+    async fn test_mock_loader() {
+        let loader = MockLoader::new(|k: &i8| if *k < 0 { Err("negative") } else { Ok(*k * 2) });
+        let values = assert_batches(
+            loader.recorder(),
+            vec![vec![1, 2]],
+            loader.load_many_ordered(vec![2, 1]),
+        )
+        .await;
+        assert_eq!(values, Ok(vec![4, 2]));
+        assert!(loader.load(-1).await.is_err());
+        assert_eq!(loader.recorder().keys(), vec![2, 1, -1]);
+        assert_eq!(loader.recorder().take().len(), 2);
+        assert_eq!(loader.recorder().calls(), 0);
+    }
+
+    #[test]
+    fn test_mock_timed_cache() {
+        let clock = MockClock::new();
+        let mut cache = MockTimedCache::with_lifespan(&clock, 10);
+        cache.cache_set(1, "one");
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(cache.cache_get(&1), Some(&"one"));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.cache_get(&1), None);
+        assert_eq!(cache.cache_size(), 0);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::cacher::{CacheKey, CacheVal};
+use crate::error::LoaderError;
+use crate::loaders::{load_uncached, CacheControl, CachedLoader, Loader};
+use std::fmt::Debug;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+type KeyProducer<K> = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Vec<K>> + Send>> + Send + Sync>;
+type ReportFn<K, E> = Arc<dyn Fn(&WarmupReport<K, E>) + Send + Sync>;
+
+const DEFAULT_CHUNK_SIZE: usize = 100;
+
+/// Preloads keys into the cache of a `CachedLoader` and keeps them fresh.
+///
+/// ```ignore
+/// let warmup = Warmup::new(RatesLoader::new(), pairs).chunk_size(50);
+/// // fill the cache before serving requests
+/// let report = warmup.run().await;
+/// // reload the same keys every minute
+/// let handle = warmup.spawn_refresh(Duration::from_secs(60));
+/// ...
+/// handle.shutdown().await;
+/// ```
+pub struct Warmup<K, V, L: CachedLoader<K, V>>
+where
+    K: CacheKey,
+    V: CacheVal,
+{
+    loader: L,
+    keys: KeyProducer<K>,
+    chunk_size: usize,
+    on_report: Option<ReportFn<K, L::Error>>,
+    _pd: PhantomData<fn() -> V>,
+}
+
+impl<K: CacheKey, V: CacheVal, L: CachedLoader<K, V>> Warmup<K, V, L> {
+    /// Warm up a fixed list of keys
+    pub fn new(loader: L, keys: Vec<K>) -> Self {
+        Self::with_key_producer(loader, move || {
+            let keys = keys.clone();
+            async move { keys }
+        })
+    }
+
+    /// Warm up keys returned by `producer`, it is called before every run,
+    /// so the list of keys can change between refreshes
+    pub fn with_key_producer<F, Fut>(loader: L, producer: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Vec<K>> + Send + 'static,
+    {
+        Warmup {
+            loader,
+            keys: Arc::new(move || Box::pin(producer())),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            on_report: None,
+            _pd: PhantomData,
+        }
+    }
+
+    /// Max number of keys passed to a single `load_many`, 100 by default
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Called with the report of every refresh made by `spawn_refresh`
+    pub fn on_report(
+        mut self,
+        f: impl Fn(&WarmupReport<K, L::Error>) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_report = Some(Arc::new(f));
+        self
+    }
+
+    /// Load all keys that aren't cached yet
+    pub async fn run(&self) -> WarmupReport<K, L::Error> {
+        self.load(false).await
+    }
+
+    /// Reload all keys, even cached ones, every `interval` in a background task.
+    ///
+    /// Cached values are replaced only with successfully loaded ones,
+    /// so they are still served if the source is down during a refresh.
+    pub fn spawn_refresh(self, interval: Duration) -> RefreshHandle {
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = &mut shutdown_rx => break,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+                let report = self.load(true).await;
+                if let Some(on_report) = &self.on_report {
+                    on_report(&report);
+                }
+            }
+        });
+        RefreshHandle {
+            shutdown_tx: Some(shutdown_tx),
+            task,
+        }
+    }
+
+    async fn load(&self, refresh: bool) -> WarmupReport<K, L::Error> {
+        let keys = (self.keys)().await;
+        let mut report = WarmupReport {
+            loaded: 0,
+            failed: vec![],
+        };
+        for chunk in keys.chunks(self.chunk_size) {
+            let result = if refresh {
+                self.reload(chunk).await
+            } else {
+                self.loader.load_many(chunk.to_vec()).await.map(|_| ())
+            };
+            match result {
+                Ok(()) => report.loaded += chunk.len(),
+                Err(e) => report.failed.push((chunk.to_vec(), e)),
+            }
+        }
+        report
+    }
+
+    // cached values stay available while the keys are reloaded
+    async fn reload(&self, keys: &[K]) -> Result<(), LoaderError<K, L::Error>> {
+        let values = load_uncached(&self.loader, keys).await?;
+        for (key, value) in values {
+            self.loader.prime(key, value).await;
+        }
+        Ok(())
+    }
+}
+
+/// Result of a warm-up or refresh run
+#[derive(Debug)]
+pub struct WarmupReport<K, E: Debug> {
+    /// Number of successfully loaded keys
+    pub loaded: usize,
+    /// Chunks of keys that failed to load, with the errors
+    pub failed: Vec<(Vec<K>, LoaderError<K, E>)>,
+}
+
+impl<K, E: Debug> WarmupReport<K, E> {
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    pub fn failed_keys(&self) -> impl Iterator<Item = &K> {
+        self.failed.iter().flat_map(|(keys, _)| keys)
+    }
+}
+
+/// Handle of the background refresh task, the task is stopped with `shutdown`
+pub struct RefreshHandle {
+    shutdown_tx: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+}
+
+impl RefreshHandle {
+    /// Stop refreshing, waiting for the current refresh to complete
+    pub async fn shutdown(mut self) {
+        if let Some(tx) = self.shutdown_tx.take() {
+            // the task may have panicked already, nothing to stop then
+            let _ = tx.send(());
+        }
+        let _ = (&mut self.task).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UnboundCache;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone)]
+    struct Rates;
+
+    #[async_trait]
+    impl CachedLoader<(u8, u8), f32> for Rates {
+        type Cache = UnboundCache<(u8, u8), f32>;
+        type Error = String;
+
+        async fn load_fn(&mut self, keys: &[(u8, u8)]) -> Result<Vec<f32>, Self::Error> {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            if keys.iter().any(|(a, b)| a == b) {
+                return Err("same assets".to_string());
+            }
+            Ok(keys.iter().map(|(a, b)| *a as f32 / *b as f32).collect())
+        }
+
+        fn init_cache() -> Self::Cache {
+            UnboundCache::new()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_warmup() {
+        let keys = vec![(1, 2), (3, 3), (1, 4), (2, 1)];
+        let warmup = Warmup::new(Rates, keys).chunk_size(2);
+
+        let report = warmup.run().await;
+        assert_eq!(report.loaded, 2);
+        assert_eq!(
+            report.failed_keys().collect::<Vec<_>>(),
+            vec![&(1, 2), &(3, 3)]
+        );
+        assert_eq!(CALLS.swap(0, Ordering::SeqCst), 2);
+
+        //loaded keys are cached, the failed chunk is loaded again
+        assert_eq!(Rates.load((2, 1)).await, Ok(2.0));
+        warmup.run().await;
+        assert_eq!(CALLS.swap(0, Ordering::SeqCst), 1);
+
+        //refresh reloads cached keys too
+        let (reports_tx, mut reports_rx) = tokio::sync::mpsc::unbounded_channel();
+        let producer_calls = Arc::new(AtomicUsize::new(0));
+        let producer_calls_ = producer_calls.clone();
+        let handle = Warmup::with_key_producer(Rates, move || {
+            producer_calls_.fetch_add(1, Ordering::SeqCst);
+            async { vec![(1, 4), (2, 1)] }
+        })
+        .on_report(move |report| reports_tx.send(report.loaded).unwrap())
+        .spawn_refresh(Duration::from_millis(10));
+
+        assert_eq!(reports_rx.recv().await, Some(2));
+        assert_eq!(reports_rx.recv().await, Some(2));
+        handle.shutdown().await;
+        let refreshes = producer_calls.load(Ordering::SeqCst);
+        assert_eq!(refreshes, 2);
+        assert_eq!(CALLS.swap(0, Ordering::SeqCst), 2);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(producer_calls.load(Ordering::SeqCst), refreshes);
+    }
+
+    static SOURCE_DOWN: AtomicBool = AtomicBool::new(false);
+
+    #[derive(Clone)]
+    struct Prices;
+
+    #[async_trait]
+    impl CachedLoader<u16, u64> for Prices {
+        type Cache = UnboundCache<u16, u64>;
+        type Error = ();
+
+        async fn load_fn(&mut self, keys: &[u16]) -> Result<Vec<u64>, Self::Error> {
+            if SOURCE_DOWN.load(Ordering::SeqCst) {
+                return Err(());
+            }
+            Ok(keys.iter().map(|k| *k as u64 * 10).collect())
+        }
+
+        fn init_cache() -> Self::Cache {
+            UnboundCache::new()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_refresh_keeps_cache() {
+        let warmup = Warmup::new(Prices, vec![1, 2]);
+        assert!(warmup.run().await.is_ok());
+
+        SOURCE_DOWN.store(true, Ordering::SeqCst);
+        let report = warmup.load(true).await;
+        SOURCE_DOWN.store(false, Ordering::SeqCst);
+        assert_eq!(report.failed_keys().count(), 2);
+        assert_eq!(Prices.peek(&1).await, Some(10));
+        assert_eq!(Prices.peek(&2).await, Some(20));
+    }
+}
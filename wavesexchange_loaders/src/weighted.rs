@@ -0,0 +1,511 @@
+use crate::cacher::{CacheBounds, CacheKey, CacheVal};
+use cached::Cached;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+
+/// Approximate size of a cached entry, in bytes or any other unit of `WeightedCache` capacity
+pub trait Weigher<K, V>: Send + Sync + 'static {
+    fn weigh(&self, key: &K, value: &V) -> usize;
+}
+
+impl<K, V, F> Weigher<K, V> for F
+where
+    F: Fn(&K, &V) -> usize + Send + Sync + 'static,
+{
+    fn weigh(&self, key: &K, value: &V) -> usize {
+        self(key, value)
+    }
+}
+
+/// Which entries a full `WeightedCache` keeps
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Admission {
+    /// Every new entry is cached, least recently used entries are evicted to fit it
+    Lru,
+    /// A new entry is cached only if it is used more often than every entry
+    /// it would evict, so a burst of one-off keys doesn't flush the hot ones
+    TinyLfu,
+}
+
+/// Cache bounded by the total weight of its entries instead of their count.
+///
+/// ```ignore
+/// impl CachedLoader<String, StateChanges> for StateChangesLoader {
+///     type Cache = WeightedCache<String, StateChanges, fn(&String, &StateChanges) -> usize>;
+///
+///     fn init_cache() -> Self::Cache {
+///         // keep at most ~64 MiB of state changes
+///         WeightedCache::with_max_weight(64 << 20, |k, v| k.len() + v.approx_size())
+///     }
+/// }
+/// ```
+///
+/// An entry heavier than the whole capacity is never cached,
+/// loaders still return values that the cache didn't keep.
+/// Weights are computed on insert, changes made through `cache_get_mut` aren't accounted.
+pub struct WeightedCache<K, V, W> {
+    max_weight: usize,
+    weight: usize,
+    weigher: W,
+    entries: HashMap<K, Entry<V>>,
+    // access tick -> key, the first entry is the least recently used one
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    sketch: Option<FrequencySketch>,
+    hits: u64,
+    misses: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    weight: usize,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V, W: Weigher<K, V>> WeightedCache<K, V, W> {
+    /// LRU cache holding entries of at most `max_weight` total weight
+    pub fn with_max_weight(max_weight: usize, weigher: W) -> Self {
+        WeightedCache {
+            max_weight,
+            weight: 0,
+            weigher,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            sketch: None,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn with_admission(mut self, admission: Admission) -> Self {
+        self.sketch = match admission {
+            Admission::Lru => None,
+            Admission::TinyLfu => Some(FrequencySketch::new()),
+        };
+        self
+    }
+
+    /// Total weight of cached entries
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub fn max_weight(&self) -> usize {
+        self.max_weight
+    }
+
+    /// Cached keys from the least to the most recently used
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.order.values()
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn touch(&mut self, key: &K) {
+        if let Some(sketch) = &mut self.sketch {
+            sketch.increment(key);
+        }
+        let tick = self.next_tick();
+        if let Some(entry) = self.entries.get_mut(key) {
+            let key = self
+                .order
+                .remove(&entry.tick)
+                .expect("cached key is ordered");
+            entry.tick = tick;
+            self.order.insert(tick, key);
+        }
+    }
+
+    /// Whether a new value of `weight` can be cached for `key`
+    fn admits(&self, key: &K, weight: usize) -> bool {
+        if weight > self.max_weight {
+            return false;
+        }
+        // the previous value of the key is replaced, not evicted
+        let mut freed = self.entries.get(key).map_or(0, |e| e.weight);
+        let mut victims = vec![];
+        let mut lru = self.order.values().filter(|k| *k != key);
+        while self.weight - freed + weight > self.max_weight {
+            let victim = lru.next().expect("weight of cached entries adds up");
+            freed += self.entries[victim].weight;
+            victims.push(victim.clone());
+        }
+        match &self.sketch {
+            Some(sketch) => {
+                let frequency = sketch.frequency(key);
+                victims.iter().all(|v| sketch.frequency(v) < frequency)
+            }
+            None => true,
+        }
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.tick);
+        self.weight -= entry.weight;
+        Some(entry.value)
+    }
+
+    /// Insert regardless of admission, evicting other entries to fit
+    fn insert(&mut self, key: K, value: V, weight: usize) -> Option<V> {
+        let old = self.remove(&key);
+        let victims = self
+            .order
+            .values()
+            .scan(self.weight, |total, k| {
+                let over = *total + weight > self.max_weight;
+                *total -= self.entries[k].weight;
+                over.then(|| k.clone())
+            })
+            .collect::<Vec<_>>();
+        for victim in victims {
+            self.remove(&victim);
+        }
+        let tick = self.next_tick();
+        self.weight += weight;
+        self.order.insert(tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                weight,
+                tick,
+            },
+        );
+        old
+    }
+}
+
+impl<K: CacheKey, V: CacheVal, W: Weigher<K, V>> CacheBounds<K, V> for WeightedCache<K, V, W> {
+    fn cache_keeps(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+}
+
+impl<K: Hash + Eq + Clone, V, W: Weigher<K, V>> Cached<K, V> for WeightedCache<K, V, W> {
+    fn cache_get(&mut self, k: &K) -> Option<&V> {
+        self.cache_get_mut(k).map(|v| &*v)
+    }
+
+    fn cache_get_mut(&mut self, k: &K) -> Option<&mut V> {
+        self.touch(k);
+        match self.entries.get_mut(k) {
+            Some(entry) => {
+                self.hits += 1;
+                Some(&mut entry.value)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn cache_set(&mut self, k: K, v: V) -> Option<V> {
+        if let Some(sketch) = &mut self.sketch {
+            sketch.increment(&k);
+        }
+        let weight = self.weigher.weigh(&k, &v);
+        if self.admits(&k, weight) {
+            self.insert(k, v, weight)
+        } else {
+            // the previous value is outdated anyway
+            self.remove(&k)
+        }
+    }
+
+    /// The value is cached even if it isn't admitted, as a reference to it is returned
+    fn cache_get_or_set_with<F: FnOnce() -> V>(&mut self, k: K, f: F) -> &mut V {
+        self.touch(&k);
+        if self.entries.contains_key(&k) {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+            let v = f();
+            let weight = self.weigher.weigh(&k, &v);
+            self.insert(k.clone(), v, weight);
+        }
+        &mut self
+            .entries
+            .get_mut(&k)
+            .expect("the key was just cached")
+            .value
+    }
+
+    fn cache_remove(&mut self, k: &K) -> Option<V> {
+        self.remove(k)
+    }
+
+    fn cache_clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.weight = 0;
+    }
+
+    fn cache_reset(&mut self) {
+        self.entries = HashMap::new();
+        self.order = BTreeMap::new();
+        self.weight = 0;
+        if let Some(sketch) = &mut self.sketch {
+            *sketch = FrequencySketch::new();
+        }
+    }
+
+    fn cache_reset_metrics(&mut self) {
+        self.hits = 0;
+        self.misses = 0;
+    }
+
+    fn cache_size(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn cache_hits(&self) -> Option<u64> {
+        Some(self.hits)
+    }
+
+    fn cache_misses(&self) -> Option<u64> {
+        Some(self.misses)
+    }
+
+    fn cache_capacity(&self) -> Option<usize> {
+        Some(self.max_weight)
+    }
+}
+
+#[cfg(feature = "snapshot")]
+impl<K: Clone, V: Clone, W> crate::snapshot::CacheEntries<K, V> for WeightedCache<K, V, W>
+where
+    K: Hash + Eq,
+{
+    fn entries(&self) -> Vec<(K, V, Option<std::time::Duration>)> {
+        self.order
+            .values()
+            .map(|k| (k.clone(), self.entries[k].value.clone(), None))
+            .collect()
+    }
+}
+
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_WIDTH: usize = 1 << 12;
+// counters are halved after this many increments, so old popularity fades out
+const SKETCH_SAMPLE: usize = SKETCH_WIDTH * 10;
+
+/// Count-min sketch estimating how often keys were used
+struct FrequencySketch {
+    counters: Vec<[u8; SKETCH_WIDTH]>,
+    hashers: [RandomState; SKETCH_DEPTH],
+    increments: usize,
+}
+
+impl FrequencySketch {
+    fn new() -> Self {
+        FrequencySketch {
+            counters: vec![[0; SKETCH_WIDTH]; SKETCH_DEPTH],
+            hashers: Default::default(),
+            increments: 0,
+        }
+    }
+
+    fn index<K: Hash>(&self, row: usize, key: &K) -> usize {
+        self.hashers[row].hash_one(key) as usize % SKETCH_WIDTH
+    }
+
+    fn frequency<K: Hash>(&self, key: &K) -> u8 {
+        (0..SKETCH_DEPTH)
+            .map(|row| self.counters[row][self.index(row, key)])
+            .min()
+            .unwrap_or_default()
+    }
+
+    fn increment<K: Hash>(&mut self, key: &K) {
+        for row in 0..SKETCH_DEPTH {
+            let i = self.index(row, key);
+            self.counters[row][i] = self.counters[row][i].saturating_add(1);
+        }
+        self.increments += 1;
+        if self.increments >= SKETCH_SAMPLE {
+            self.increments /= 2;
+            self.counters
+                .iter_mut()
+                .flat_map(|row| row.iter_mut())
+                .for_each(|c| *c /= 2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_batches, assert_cached, Recorder};
+    use crate::{global_metrics, CacheControl, CachedLoader, Loader};
+    use std::any::type_name;
+
+    type Cache = WeightedCache<u8, String, fn(&u8, &String) -> usize>;
+
+    fn cache(max_weight: usize) -> Cache {
+        WeightedCache::with_max_weight(max_weight, |_, v: &String| v.len())
+    }
+
+    fn keys(cache: &Cache) -> Vec<u8> {
+        cache.keys().copied().collect()
+    }
+
+    // usable as `CachedLoader::Cache`
+    const _: fn() = || {
+        fn cache_bounds<C: crate::cacher::CacheBounds<u8, String>>() {}
+        cache_bounds::<Cache>();
+    };
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut cache = cache(10);
+        cache.cache_set(1, "aaa".to_string());
+        cache.cache_set(2, "bbb".to_string());
+        cache.cache_set(3, "ccc".to_string());
+        assert_eq!(cache.weight(), 9);
+
+        //1 is used, so 2 is the least recently used one
+        assert!(cache.cache_get(&1).is_some());
+        cache.cache_set(4, "dd".to_string());
+        assert_eq!(keys(&cache), vec![3, 1, 4]);
+        assert_eq!(cache.weight(), 8);
+
+        //a heavy entry evicts as many entries as needed
+        cache.cache_set(5, "eeeeeee".to_string());
+        assert_eq!(keys(&cache), vec![4, 5]);
+        assert_eq!(cache.weight(), 9);
+
+        //replacing a value updates its weight
+        cache.cache_set(4, "d".to_string());
+        assert_eq!(keys(&cache), vec![5, 4]);
+        assert_eq!(cache.weight(), 8);
+
+        //entries heavier than the cache are never cached
+        assert_eq!(cache.cache_set(6, "f".repeat(11)), None);
+        assert_eq!(keys(&cache), vec![5, 4]);
+        assert_eq!(
+            cache.cache_set(5, "f".repeat(11)),
+            Some("eeeeeee".to_string())
+        );
+        assert_eq!(keys(&cache), vec![4]);
+
+        assert_eq!(cache.cache_remove(&4), Some("d".to_string()));
+        assert_eq!(cache.weight(), 0);
+        assert_eq!(cache.cache_size(), 0);
+    }
+
+    #[test]
+    fn test_tiny_lfu_admission() {
+        let mut cache = cache(6).with_admission(Admission::TinyLfu);
+        cache.cache_set(1, "aaa".to_string());
+        cache.cache_set(2, "bbb".to_string());
+        for _ in 0..3 {
+            cache.cache_get(&1);
+            cache.cache_get(&2);
+        }
+
+        //one-off keys don't replace the popular ones
+        for key in 3..10 {
+            cache.cache_set(key, "ccc".to_string());
+        }
+        assert_eq!(keys(&cache), vec![1, 2]);
+
+        //but a key used often enough does, evicting the least recently used entry
+        for _ in 0..5 {
+            cache.cache_get(&3);
+        }
+        cache.cache_set(3, "ccc".to_string());
+        assert_eq!(keys(&cache), vec![2, 3]);
+    }
+
+    // values weigh 3, so 2 of them fit
+    #[derive(Clone)]
+    struct Heavy {
+        recorder: Recorder<u16>,
+    }
+
+    #[async_trait]
+    impl CachedLoader<u16, String> for Heavy {
+        type Cache = WeightedCache<u16, String, fn(&u16, &String) -> usize>;
+        type Error = ();
+
+        async fn load_fn(&mut self, keys: &[u16]) -> Result<Vec<String>, ()> {
+            self.recorder.record(keys);
+            Ok(keys.iter().map(|k| format!("v{:02}", k)).collect())
+        }
+
+        fn init_cache() -> Self::Cache {
+            WeightedCache::with_max_weight(6, |_, v: &String| v.len())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loader_batch_heavier_than_cache() {
+        let loader = Heavy {
+            recorder: Recorder::new(),
+        };
+        let values = loader.load_many_ordered(vec![1, 2, 3, 4]).await;
+        assert_eq!(
+            values,
+            Ok(vec!["v01", "v02", "v03", "v04"]
+                .into_iter()
+                .map(String::from)
+                .collect())
+        );
+        assert_eq!(loader.peek_many(&[1, 2, 3, 4]).await.len(), 2);
+        let stats = global_metrics().stats(type_name::<Heavy>()).unwrap();
+        assert_eq!(stats.cache_evictions, 2);
+    }
+
+    #[derive(Clone)]
+    struct Popular {
+        recorder: Recorder<u32>,
+    }
+
+    #[async_trait]
+    impl CachedLoader<u32, String> for Popular {
+        type Cache = WeightedCache<u32, String, fn(&u32, &String) -> usize>;
+        type Error = ();
+
+        async fn load_fn(&mut self, keys: &[u32]) -> Result<Vec<String>, ()> {
+            self.recorder.record(keys);
+            Ok(keys.iter().map(|k| format!("v{:02}", k)).collect())
+        }
+
+        fn init_cache() -> Self::Cache {
+            let weigher: fn(&u32, &String) -> usize = |_, v| v.len();
+            WeightedCache::with_max_weight(6, weigher).with_admission(Admission::TinyLfu)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loader_tiny_lfu_full_cache() {
+        let loader = Popular {
+            recorder: Recorder::new(),
+        };
+        let recorder = &loader.recorder;
+        loader.load_many(vec![1, 2]).await.unwrap();
+        for _ in 0..3 {
+            assert_cached(recorder, loader.load_many(vec![1, 2]))
+                .await
+                .unwrap();
+        }
+
+        //a new key isn't admitted to the full cache, but is still loaded
+        let value = assert_batches(recorder, vec![vec![3]], loader.load(3)).await;
+        assert_eq!(value, Ok("v03".to_string()));
+        assert!(!loader.is_cached(&3).await);
+        assert!(loader.is_cached(&1).await);
+        assert!(loader.is_cached(&2).await);
+
+        //a rejected value isn't an eviction
+        let stats = global_metrics().stats(type_name::<Popular>()).unwrap();
+        assert_eq!(stats.cache_evictions, 0);
+    }
+}
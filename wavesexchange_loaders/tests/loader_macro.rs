@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use wavesexchange_loaders::{loader, Loader};
+
+#[derive(Clone, Default)]
+struct Ctx {
+    calls: Arc<AtomicUsize>,
+}
+
+#[loader(cache = "timed(30)", batch = 2)]
+async fn load_squares(ctx: &Ctx, keys: &[u32]) -> Result<Vec<u64>, String> {
+    ctx.calls.fetch_add(1, Ordering::SeqCst);
+    Ok(keys.iter().map(|k| (*k as u64).pow(2)).collect())
+}
+
+#[loader(name = "Names")]
+async fn load_names(keys: &[u32]) -> Result<HashMap<u32, String>, ()> {
+    Ok(keys.iter().map(|k| (*k, format!("#{}", k))).collect())
+}
+
+#[tokio::test]
+async fn test_generated_loaders() {
+    let ctx = Ctx::default();
+    let loader = LoadSquares::new(ctx.clone());
+    assert_eq!(
+        loader.load_many_ordered(vec![1, 2, 3]).await,
+        Ok(vec![1, 4, 9])
+    );
+    //batches of 2 keys
+    assert_eq!(ctx.calls.load(Ordering::SeqCst), 2);
+    //cached
+    assert_eq!(loader.load(3).await, Ok(9));
+    assert_eq!(ctx.calls.load(Ordering::SeqCst), 2);
+
+    assert_eq!(Names.load(7).await, Ok("#7".to_string()));
+    //the function is left untouched
+    assert_eq!(load_names(&[1]).await.unwrap()[&1], "#1");
+}
+
+#[test]
+fn test_compile() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/pass_*.rs");
+    t.compile_fail("tests/ui/fail_*.rs");
+}
@@ -0,0 +1,23 @@
+use wavesexchange_loaders::loader;
+
+#[loader(cache = "timed(soon)")]
+async fn load_timed(keys: &[u8]) -> Result<Vec<u8>, ()> {
+    Ok(keys.to_vec())
+}
+
+#[loader(cache = "lru(10)")]
+async fn load_lru(keys: &[u8]) -> Result<Vec<u8>, ()> {
+    Ok(keys.to_vec())
+}
+
+#[loader(batch = "10")]
+async fn load_batch(keys: &[u8]) -> Result<Vec<u8>, ()> {
+    Ok(keys.to_vec())
+}
+
+#[loader(ttl = 10)]
+async fn load_ttl(keys: &[u8]) -> Result<Vec<u8>, ()> {
+    Ok(keys.to_vec())
+}
+
+fn main() {}
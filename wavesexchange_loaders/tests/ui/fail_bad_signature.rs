@@ -0,0 +1,23 @@
+use wavesexchange_loaders::loader;
+
+#[loader]
+async fn keys_not_slice(keys: Vec<u8>) -> Result<Vec<u8>, ()> {
+    Ok(keys)
+}
+
+#[loader]
+async fn ctx_by_value(ctx: String, keys: &[u8]) -> Result<Vec<u8>, ()> {
+    Ok(keys.to_vec())
+}
+
+#[loader]
+async fn not_result(keys: &[u8]) -> Vec<u8> {
+    keys.to_vec()
+}
+
+#[loader]
+async fn not_vec(keys: &[u8]) -> Result<Option<u8>, ()> {
+    Ok(keys.first().copied())
+}
+
+fn main() {}
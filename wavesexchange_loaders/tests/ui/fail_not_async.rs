@@ -0,0 +1,8 @@
+use wavesexchange_loaders::loader;
+
+#[loader(cache = "unbound")]
+fn load(keys: &[u8]) -> Result<Vec<u8>, ()> {
+    Ok(keys.to_vec())
+}
+
+fn main() {}
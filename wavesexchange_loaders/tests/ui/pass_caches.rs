@@ -0,0 +1,45 @@
+use std::sync::Arc;
+use wavesexchange_loaders::{loader, CachedLoader, NonCachedLoader};
+
+pub struct Db;
+
+#[loader(cache = "sized(100)")]
+async fn sized(_db: &Arc<Db>, keys: &[String]) -> Result<Vec<usize>, ()> {
+    Ok(keys.iter().map(String::len).collect())
+}
+
+#[loader(cache = "timed_sized(100, 5)", batch = 10)]
+pub async fn timed_sized(keys: &[i64]) -> Result<Vec<Option<i64>>, std::io::Error> {
+    Ok(keys.iter().map(|k| k.checked_neg()).collect())
+}
+
+#[loader(cache = "unbound")]
+async fn unbound(keys: &[u8]) -> Result<Vec<u8>, ()> {
+    Ok(keys.to_vec())
+}
+
+#[loader(cache = "none", batch = 5)]
+async fn not_cached(keys: &[u8]) -> Result<Vec<u8>, ()> {
+    Ok(keys.to_vec())
+}
+
+fn assert_cached<K, V, L: CachedLoader<K, V>>(_: L)
+where
+    K: Eq + std::hash::Hash + Clone + std::fmt::Debug + Send + Sync + 'static,
+    V: Clone + std::fmt::Debug + Send + Sync + 'static,
+{
+}
+
+fn assert_not_cached<K, V, L: NonCachedLoader<K, V>>(_: L)
+where
+    K: Eq + std::hash::Hash + Clone + std::fmt::Debug + Send + Sync + 'static,
+    V: Clone + std::fmt::Debug + Send + Sync + 'static,
+{
+}
+
+fn main() {
+    assert_cached(Sized::new(Arc::new(Db)));
+    assert_cached(TimedSized);
+    assert_cached(Unbound);
+    assert_not_cached(NotCached);
+}
@@ -0,0 +1,357 @@
+/*!
+Procedural macros for `wavesexchange_loaders`, use them through the re-exports of that crate.
+*/
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, AttributeArgs, Error, FnArg, GenericArgument, Ident, ItemFn, Lit, LitStr,
+    Meta, NestedMeta, PathArguments, ReturnType, Type,
+};
+
+/// Define a loader from an async function.
+///
+/// ```ignore
+/// #[loader(cache = "timed(30)", batch = 100)]
+/// async fn load_assets(ctx: &Ctx, ids: &[String]) -> Result<Vec<Asset>, AppError> {
+///     ...
+/// }
+///
+/// let assets = LoadAssets::new(ctx).load_many(ids).await?;
+/// ```
+///
+/// The function is left as is, and a struct named after it in CamelCase
+/// (or `name = "..."`) is generated, implementing `CachedLoader` or `NonCachedLoader`.
+/// The struct holds the context (the first argument, if there are two of them),
+/// which must be `Clone + Send + Sync + 'static`.
+///
+/// The function returns values either in the order of keys (`Result<Vec<V>, E>`)
+/// or as a map (`Result<HashMap<K, V>, E>`), in which case `CachedMapLoader`
+/// or `NonCachedMapLoader` is implemented instead.
+///
+/// Params:
+/// - `cache`: `"timed(secs)"`, `"sized(size)"`, `"timed_sized(size, secs)"`, `"unbound"`
+///   or `"none"` (default, the loader is not cached)
+/// - `batch`: max number of keys passed to the function at once
+/// - `name`: name of the generated struct
+#[proc_macro_attribute]
+pub fn loader(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as AttributeArgs);
+    let item = parse_macro_input!(item as ItemFn);
+    expand(args, item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+enum CacheKind {
+    None,
+    Timed(u64),
+    Sized(usize),
+    TimedSized(usize, u64),
+    Unbound,
+}
+
+struct Params {
+    cache: CacheKind,
+    batch: Option<usize>,
+    name: Option<Ident>,
+}
+
+enum Values {
+    Ordered(Type),
+    Map(Type),
+}
+
+fn expand(args: AttributeArgs, item: ItemFn) -> Result<TokenStream2, Error> {
+    let params = parse_params(args)?;
+    let sig = &item.sig;
+    if sig.asyncness.is_none() {
+        return Err(Error::new_spanned(
+            sig.fn_token,
+            "loader function must be async",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &sig.generics,
+            "loader function can't be generic",
+        ));
+    }
+
+    let (ctx_ty, keys_ty) = match sig.inputs.iter().collect::<Vec<_>>().as_slice() {
+        [keys] => (None, keys_arg(keys)?),
+        [ctx, keys] => (Some(ctx_arg(ctx)?), keys_arg(keys)?),
+        _ => {
+            return Err(Error::new_spanned(
+                &sig.inputs,
+                "expected `(keys: &[K])` or `(ctx: &Ctx, keys: &[K])` arguments",
+            ))
+        }
+    };
+    let (values, error_ty) = return_type(&sig.output)?;
+
+    let vis = &item.vis;
+    let fn_name = &sig.ident;
+    let name = params
+        .name
+        .unwrap_or_else(|| Ident::new(&camel_case(&fn_name.to_string()), fn_name.span()));
+    let krate = quote!(::wavesexchange_loaders);
+
+    let (strukt, call) = match &ctx_ty {
+        Some(ctx_ty) => (
+            quote! {
+                #[derive(Clone)]
+                #vis struct #name {
+                    pub ctx: #ctx_ty,
+                }
+
+                impl #name {
+                    #vis fn new(ctx: #ctx_ty) -> Self {
+                        #name { ctx }
+                    }
+                }
+            },
+            quote!(#fn_name(&self.ctx, keys).await),
+        ),
+        None => (
+            quote! {
+                #[derive(Clone)]
+                #vis struct #name;
+            },
+            quote!(#fn_name(keys).await),
+        ),
+    };
+
+    let (value_ty, load_fn, traits) = match &values {
+        Values::Ordered(v) => (
+            v,
+            quote! {
+                async fn load_fn(&mut self, keys: &[#keys_ty]) -> ::std::result::Result<::std::vec::Vec<#v>, Self::Error> {
+                    #call
+                }
+            },
+            (quote!(CachedLoader), quote!(NonCachedLoader)),
+        ),
+        Values::Map(v) => (
+            v,
+            quote! {
+                async fn load_fn_map(
+                    &mut self,
+                    keys: &[#keys_ty],
+                ) -> ::std::result::Result<::std::collections::HashMap<#keys_ty, #v>, Self::Error> {
+                    #call
+                }
+            },
+            (quote!(CachedMapLoader), quote!(NonCachedMapLoader)),
+        ),
+    };
+    let (cached_trait, non_cached_trait) = traits;
+
+    let init_loader = |inner: TokenStream2| {
+        params.batch.map(|batch| {
+            quote! {
+                fn init_loader(
+                    loader: #krate::#inner<#keys_ty, #value_ty, Self>,
+                ) -> #krate::#inner<#keys_ty, #value_ty, Self> {
+                    loader.with_max_batch_size(#batch)
+                }
+            }
+        })
+    };
+
+    let cache = match params.cache {
+        CacheKind::None => None,
+        CacheKind::Timed(secs) => Some((format_ident!("TimedCache"), quote!(with_lifespan(#secs)))),
+        CacheKind::Sized(size) => Some((format_ident!("SizedCache"), quote!(with_size(#size)))),
+        CacheKind::TimedSized(size, secs) => Some((
+            format_ident!("TimedSizedCache"),
+            quote!(with_size_and_lifespan(#size, #secs)),
+        )),
+        CacheKind::Unbound => Some((format_ident!("UnboundCache"), quote!(new()))),
+    };
+
+    let loader_impl = match cache {
+        Some((cache_ty, cache_init)) => {
+            let init_loader = init_loader(quote!(InnerCachedLoader));
+            quote! {
+                #[#krate::async_trait]
+                impl #krate::#cached_trait<#keys_ty, #value_ty> for #name {
+                    type Cache = #krate::#cache_ty<#keys_ty, #value_ty>;
+                    type Error = #error_ty;
+
+                    #load_fn
+
+                    fn init_cache() -> Self::Cache {
+                        #krate::#cache_ty::#cache_init
+                    }
+
+                    #init_loader
+                }
+            }
+        }
+        None => {
+            let init_loader = init_loader(quote!(InnerLoader));
+            quote! {
+                #[#krate::async_trait]
+                impl #krate::#non_cached_trait<#keys_ty, #value_ty> for #name {
+                    type Error = #error_ty;
+
+                    #load_fn
+
+                    #init_loader
+                }
+            }
+        }
+    };
+
+    Ok(quote! {
+        #item
+
+        #strukt
+
+        #loader_impl
+    })
+}
+
+fn parse_params(args: AttributeArgs) -> Result<Params, Error> {
+    let mut params = Params {
+        cache: CacheKind::None,
+        batch: None,
+        name: None,
+    };
+    for arg in args {
+        let nv = match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) => nv,
+            other => return Err(Error::new_spanned(other, "expected `param = value`")),
+        };
+        let param = nv
+            .path
+            .get_ident()
+            .map(Ident::to_string)
+            .unwrap_or_default();
+        match (param.as_str(), &nv.lit) {
+            ("cache", Lit::Str(s)) => params.cache = parse_cache(s)?,
+            ("batch", Lit::Int(i)) => params.batch = Some(i.base10_parse()?),
+            ("name", Lit::Str(s)) => params.name = Some(s.parse()?),
+            ("cache" | "name", lit) => return Err(Error::new_spanned(lit, "expected a string")),
+            ("batch", lit) => return Err(Error::new_spanned(lit, "expected an integer")),
+            _ => {
+                return Err(Error::new_spanned(
+                    nv.path,
+                    "unknown param, expected `cache`, `batch` or `name`",
+                ))
+            }
+        }
+    }
+    Ok(params)
+}
+
+fn parse_cache(lit: &LitStr) -> Result<CacheKind, Error> {
+    let value = lit.value().replace(' ', "");
+    let (kind, args) = match value.split_once('(') {
+        Some((kind, args)) => match args.strip_suffix(')') {
+            Some(args) => (kind, args.split(',').collect::<Vec<_>>()),
+            None => return Err(Error::new_spanned(lit, "unclosed parenthesis")),
+        },
+        None => (value.as_str(), vec![]),
+    };
+    let num = |s: &str| {
+        s.parse::<u64>()
+            .map_err(|_| Error::new_spanned(lit, format!("`{}` is not a number", s)))
+    };
+    match (kind, args.as_slice()) {
+        ("none", []) => Ok(CacheKind::None),
+        ("unbound", []) => Ok(CacheKind::Unbound),
+        ("timed", [secs]) => Ok(CacheKind::Timed(num(secs)?)),
+        ("sized", [size]) => Ok(CacheKind::Sized(num(size)? as usize)),
+        ("timed_sized", [size, secs]) => Ok(CacheKind::TimedSized(num(size)? as usize, num(secs)?)),
+        _ => Err(Error::new_spanned(
+            lit,
+            "expected one of `timed(secs)`, `sized(size)`, `timed_sized(size, secs)`, `unbound`, `none`",
+        )),
+    }
+}
+
+fn ctx_arg(arg: &FnArg) -> Result<Type, Error> {
+    match typed_arg(arg)? {
+        Type::Reference(r) if r.mutability.is_none() => Ok((*r.elem).clone()),
+        ty => Err(Error::new_spanned(
+            ty,
+            "expected context passed by reference: `&Ctx`",
+        )),
+    }
+}
+
+fn keys_arg(arg: &FnArg) -> Result<Type, Error> {
+    match typed_arg(arg)? {
+        Type::Reference(r) if r.mutability.is_none() => match &*r.elem {
+            Type::Slice(s) => Ok((*s.elem).clone()),
+            _ => Err(Error::new_spanned(r, "expected keys slice: `&[K]`")),
+        },
+        ty => Err(Error::new_spanned(ty, "expected keys slice: `&[K]`")),
+    }
+}
+
+fn typed_arg(arg: &FnArg) -> Result<&Type, Error> {
+    match arg {
+        FnArg::Typed(t) => Ok(&t.ty),
+        FnArg::Receiver(r) => Err(Error::new_spanned(r, "loader function can't take `self`")),
+    }
+}
+
+/// Parse `Result<Vec<V>, E>` or `Result<HashMap<K, V>, E>`
+fn return_type(output: &ReturnType) -> Result<(Values, Type), Error> {
+    const EXPECTED: &str = "expected `Result<Vec<V>, E>` or `Result<HashMap<K, V>, E>`";
+    let ty = match output {
+        ReturnType::Type(_, ty) => ty,
+        ReturnType::Default => return Err(Error::new(Span::call_site(), EXPECTED)),
+    };
+    let err = || Error::new_spanned(ty, EXPECTED);
+    let (ok_ty, error_ty) = match generic_args(ty, "Result").as_deref() {
+        Some([ok_ty, error_ty]) => (ok_ty.clone(), error_ty.clone()),
+        _ => return Err(err()),
+    };
+    if let Some([v]) = generic_args(&ok_ty, "Vec").as_deref() {
+        return Ok((Values::Ordered(v.clone()), error_ty));
+    }
+    if let Some([_, v]) = generic_args(&ok_ty, "HashMap").as_deref() {
+        return Ok((Values::Map(v.clone()), error_ty));
+    }
+    Err(err())
+}
+
+/// Type arguments of `ty` if it is a path ending with `name`
+fn generic_args(ty: &Type, name: &str) -> Option<Vec<Type>> {
+    let segment = match ty {
+        Type::Path(p) => p.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != name {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => args
+            .args
+            .iter()
+            .map(|arg| match arg {
+                GenericArgument::Type(ty) => Some(ty.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
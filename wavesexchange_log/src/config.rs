@@ -0,0 +1,219 @@
+use crate::filter::{FilterDrain, LevelFilter};
+use crate::format::{local_now, OutputFormat, Service};
+use crate::queue::{Overflow, QueueDrain};
+use crate::rate_limit::{RateLimit, RateLimitDrain};
+use crate::redact::{RedactDrain, DEFAULT_KEYS};
+use crate::{Error, Fields, Root, ROOT};
+use slog::{o, Drain, Logger, OwnedKV, OwnedKVList};
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+const DEFAULT_CHAN_SIZE: usize = 1000;
+
+/// Where log records are written
+#[derive(Clone, Debug)]
+pub enum Output {
+    Stdout,
+    Stderr,
+    /// File opened for appending, created if missing
+    File(PathBuf),
+}
+
+impl Output {
+    fn open(&self) -> Result<Box<dyn Write + Send>, Error> {
+        Ok(match self {
+            Output::Stdout => Box::new(io::stdout()),
+            Output::Stderr => Box::new(io::stderr()),
+            Output::File(path) => {
+                Box::new(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+        })
+    }
+}
+
+/// Configuration of the global `LOGGER`.
+///
+/// ```no_run
+/// use wavesexchange_log::{LoggerConfig, Output};
+///
+/// LoggerConfig::from_env()?
+///     .output(Output::Stderr)
+///     .field("service", "assets")
+///     .init()?;
+/// # Ok::<(), wavesexchange_log::Error>(())
+/// ```
+///
+/// `init` must be called once at startup, before anything is logged.
+/// Without it `LOGGER` is configured from the environment on first use:
+/// `RUST_LOG_FORMAT` (`json`, `plain`, `logfmt` or `ecs`) and `RUST_LOG` level filter.
+#[derive(Clone, Debug)]
+pub struct LoggerConfig {
+    format: OutputFormat,
+    output: Output,
+    filter: String,
+    chan_size: usize,
+    overflow: Overflow,
+    rate_limit: Option<RateLimit>,
+    redact_keys: Vec<String>,
+    fields: Vec<(&'static str, String)>,
+    service: Service,
+}
+
+impl Default for LoggerConfig {
+    fn default() -> Self {
+        LoggerConfig {
+            format: OutputFormat::default(),
+            output: Output::Stdout,
+            filter: String::new(),
+            chan_size: DEFAULT_CHAN_SIZE,
+            overflow: Overflow::default(),
+            rate_limit: None,
+            redact_keys: DEFAULT_KEYS.iter().map(|k| k.to_string()).collect(),
+            fields: vec![],
+            service: Service::default(),
+        }
+    }
+}
+
+impl LoggerConfig {
+    /// JSON to stdout, only errors are logged
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Default config with format and level filter taken from `RUST_LOG_FORMAT` and `RUST_LOG`
+    pub fn from_env() -> Result<Self, Error> {
+        Ok(LoggerConfig {
+            format: OutputFormat::from_env()?,
+            filter: env::var("RUST_LOG").unwrap_or_default(),
+            ..Self::default()
+        })
+    }
+
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Level filter in the `RUST_LOG` syntax, e.g. `info,hyper=warn`
+    pub fn filter(mut self, filter: impl Into<String>) -> Self {
+        self.filter = filter.into();
+        self
+    }
+
+    /// Name of the service, written as `service.name` by the `ecs` format
+    pub fn service_name(mut self, name: impl Into<String>) -> Self {
+        self.service.name = Some(name.into());
+        self
+    }
+
+    /// Version of the service, e.g. `env!("CARGO_PKG_VERSION")`,
+    /// written as `service.version` by the `ecs` format
+    pub fn service_version(mut self, version: impl Into<String>) -> Self {
+        self.service.version = Some(version.into());
+        self
+    }
+
+    /// Capacity of the queue to the logging thread
+    pub fn chan_size(mut self, chan_size: usize) -> Self {
+        self.chan_size = chan_size;
+        self
+    }
+
+    /// What happens to records when the queue to the logging thread is full,
+    /// the newest record is dropped by default. Dropped records are counted by `dropped_records`.
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Log at most `burst` records of every logging macro call per `interval`,
+    /// the number of dropped records is logged once the interval ends
+    pub fn rate_limit(mut self, burst: u32, interval: Duration) -> Self {
+        self.rate_limit = Some(RateLimit { burst, interval });
+        self
+    }
+
+    /// Mask values of keys ending with any of these names as `_`, `.` or `-` separated segments,
+    /// ignoring case, in fields and messages, e.g. `token` masks `access_token=...` query params
+    /// but not `tokens_count` or `token_len`. An empty list disables masking.
+    ///
+    /// Defaults to `authorization`, `signature`, `seed`, `token` and `password`.
+    pub fn redact_keys<K: Into<String>>(mut self, keys: impl IntoIterator<Item = K>) -> Self {
+        self.redact_keys = keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Field added to every record, e.g. the service name
+    pub fn field(mut self, key: &'static str, value: impl Into<String>) -> Self {
+        self.fields.push((key, value.into()));
+        self
+    }
+
+    /// Build a standalone logger, `LOGGER` is not affected
+    pub fn build(self) -> Result<Logger, Error> {
+        self.build_root().map(|root| root.logger)
+    }
+
+    /// Build the logger and make it the global `LOGGER`,
+    /// its level filter can then be changed with `set_filter`.
+    ///
+    /// Fails with `Error::AlreadyInitialized` if `LOGGER` was already used or initialized.
+    pub fn init(self) -> Result<(), Error> {
+        ROOT.set(self.build_root()?)
+            .map_err(|_| Error::AlreadyInitialized)
+    }
+
+    pub(crate) fn build_root(self) -> Result<Root, Error> {
+        let filter: LevelFilter = self.filter.parse()?;
+        let filter = Arc::new(RwLock::new(filter));
+        let writer = self.output.open()?;
+        let drain = self.format.drain(writer, local_now, self.service);
+        // static fields of the root logger, for records logged by the drains themselves
+        let values = OwnedKVList::from(OwnedKV(Fields(self.fields.clone())));
+        let drain = QueueDrain::new(drain, self.chan_size, self.overflow, values.clone());
+        let queue = drain.queue();
+        let drain = RedactDrain::new(drain, &self.redact_keys).fuse();
+        let drain = RateLimitDrain::new(drain, self.rate_limit, values).fuse();
+        let drain = FilterDrain::new(drain, filter.clone()).fuse();
+        let logger = Logger::root(drain, o!());
+        let logger = if self.fields.is_empty() {
+            logger
+        } else {
+            logger.new(OwnedKV(Fields(self.fields)))
+        };
+        Ok(Root {
+            logger,
+            filter,
+            queue,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_errors() {
+        let config = LoggerConfig::new().filter("info,hyper=noisy");
+        assert!(matches!(config.build(), Err(Error::InvalidFilter(_))));
+
+        let config = LoggerConfig::new().output(Output::File("/nonexistent/dir/log".into()));
+        assert!(matches!(config.build(), Err(Error::Io(_))));
+
+        assert!(matches!(
+            "yaml".parse::<OutputFormat>(),
+            Err(Error::UnknownFormat(s)) if s == "yaml"
+        ));
+    }
+}
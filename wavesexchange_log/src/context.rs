@@ -0,0 +1,165 @@
+use crate::trace::SpanContext;
+use crate::{Fields, LOGGER};
+use slog::{Logger, OwnedKV};
+use std::future::Future;
+
+tokio::task_local! {
+    static CONTEXT: Current;
+}
+
+struct Current {
+    // logger with context fields but without span ids, so that nested spans don't repeat them
+    base: Logger,
+    logger: Logger,
+    span: Option<SpanContext>,
+}
+
+/// Fields added to every record logged by a task, e.g. request id or user address.
+///
+/// ```
+/// # use wavesexchange_log::{info, LogContext};
+/// # async fn handle(req_id: String) {
+/// LogContext::new()
+///     .with("req_id", req_id)
+///     .scope(async {
+///         info!("loading assets"); // logged with req_id
+///     })
+///     .await;
+/// # }
+/// ```
+///
+/// Scopes can be nested, fields of the inner context are added to the outer ones,
+/// and the span of the inner context, if set, replaces the outer one.
+/// Tasks spawned within a scope don't inherit its context.
+#[derive(Clone, Debug, Default)]
+pub struct LogContext {
+    fields: Vec<(&'static str, String)>,
+    span: Option<SpanContext>,
+}
+
+impl LogContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, key: &'static str, value: impl Into<String>) -> Self {
+        self.fields.push((key, value.into()));
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Add `trace_id` and `span_id` of the span to records
+    pub fn span(mut self, span: SpanContext) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn get_span(&self) -> Option<SpanContext> {
+        self.span
+    }
+
+    /// Run `fut` with this context
+    pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+        let (base, outer_span) = CONTEXT
+            .try_with(|current| (current.base.clone(), current.span))
+            .unwrap_or_else(|_| (LOGGER.clone(), None));
+        let base = base.new(OwnedKV(Fields(self.fields)));
+        let span = self.span.or(outer_span);
+        let logger = match span {
+            Some(span) => base.new(OwnedKV(Fields(vec![
+                ("trace_id", span.trace_id()),
+                ("span_id", span.span_id()),
+            ]))),
+            None => base.clone(),
+        };
+        let current = Current { base, logger, span };
+        CONTEXT.scope(current, fut).await
+    }
+}
+
+// scope replacing the logger of the current task, used to capture records in tests
+#[cfg(any(test, feature = "testing"))]
+pub(crate) async fn with_root<F: Future>(root: Logger, fut: F) -> F::Output {
+    CONTEXT.scope(Current::root(root), fut).await
+}
+
+#[cfg(any(test, feature = "testing"))]
+pub(crate) fn with_root_sync<R>(root: Logger, f: impl FnOnce() -> R) -> R {
+    CONTEXT.sync_scope(Current::root(root), f)
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Current {
+    fn root(logger: Logger) -> Self {
+        Current {
+            base: logger.clone(),
+            logger,
+            span: None,
+        }
+    }
+}
+
+/// Logger of the current `LogContext`, the global `LOGGER` outside of any
+pub fn logger() -> Logger {
+    CONTEXT
+        .try_with(|current| current.logger.clone())
+        .unwrap_or_else(|_| LOGGER.clone())
+}
+
+/// Span of the current `LogContext`
+pub fn current_span() -> Option<SpanContext> {
+    CONTEXT.try_with(|current| current.span).ok().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::{b, record, Key, Level, Serializer, KV};
+    use std::fmt::Arguments;
+
+    struct Keys(Vec<String>);
+
+    impl Serializer for Keys {
+        fn emit_arguments(&mut self, key: Key, _: &Arguments) -> slog::Result {
+            self.0.push(key.to_string());
+            Ok(())
+        }
+    }
+
+    fn context_keys() -> Vec<String> {
+        let mut keys = Keys(vec![]);
+        let args = format_args!("");
+        let record = record!(Level::Info, "", &args, b!());
+        logger().list().serialize(&record, &mut keys).unwrap();
+        keys.0
+    }
+
+    #[tokio::test]
+    async fn test_context_scope() {
+        let ctx = LogContext::new().with("req_id", "abc");
+        assert_eq!(ctx.get("req_id"), Some("abc"));
+
+        let keys = ctx
+            .scope(async {
+                let outer = context_keys();
+                let inner = LogContext::new()
+                    .with("user", "3P...")
+                    .scope(async { context_keys() })
+                    .await;
+                (outer, inner)
+            })
+            .await;
+        assert!(keys.0.contains(&"req_id".to_string()));
+        assert!(!keys.0.contains(&"user".to_string()));
+        assert!(keys.1.contains(&"req_id".to_string()));
+        assert!(keys.1.contains(&"user".to_string()));
+        assert!(!context_keys().contains(&"req_id".to_string()));
+    }
+}
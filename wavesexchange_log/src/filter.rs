@@ -0,0 +1,203 @@
+use crate::{root, Error};
+use slog::{Drain, FilterLevel, Level, OwnedKVList, Record};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+pub(crate) type SharedFilter = Arc<RwLock<LevelFilter>>;
+
+/// Level filter of the global `LOGGER` in the `RUST_LOG` syntax
+pub fn current_filter() -> String {
+    root().filter.read().unwrap().to_string()
+}
+
+/// Replace the level filter of the global `LOGGER`, e.g. `info,my_service::db=trace`
+/// to trace a module of a running service. Takes effect immediately.
+pub fn set_filter(spec: &str) -> Result<(), Error> {
+    let filter = spec.parse()?;
+    *root().filter.write().unwrap() = filter;
+    Ok(())
+}
+
+/// Level filter in the `RUST_LOG` syntax, e.g. `info,hyper=warn,my_crate::db=trace`.
+///
+/// A directive without a level enables everything for the module,
+/// the longest matching module prefix wins. Without directives only errors are logged.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct LevelFilter {
+    // sorted by module length, so the most specific directive is the last one
+    directives: Vec<Directive>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Directive {
+    module: Option<String>,
+    level: FilterLevel,
+}
+
+impl LevelFilter {
+    pub(crate) fn enabled(&self, level: Level, module: &str) -> bool {
+        let directive = self.directives.iter().rev().find(|d| match &d.module {
+            Some(name) => module.starts_with(name.as_str()),
+            None => true,
+        });
+        match directive {
+            Some(d) => d.level.accepts(level),
+            None => false,
+        }
+    }
+}
+
+impl FromStr for LevelFilter {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidFilter(spec.to_string());
+        let mut directives = vec![];
+        for part in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let mut it = part.split('=').map(str::trim);
+            let directive = match (it.next(), it.next(), it.next()) {
+                // a single word is either a global level or a module name
+                (Some(word), None, None) => match word.parse() {
+                    Ok(level) => Directive {
+                        module: None,
+                        level,
+                    },
+                    Err(_) => Directive {
+                        module: Some(word.to_string()),
+                        level: FilterLevel::max(),
+                    },
+                },
+                (Some(module), Some(level), None) if !module.is_empty() => Directive {
+                    module: Some(module.to_string()),
+                    level: level.parse().map_err(|_| invalid())?,
+                },
+                _ => return Err(invalid()),
+            };
+            if matches!(&directive.module, Some(m) if m.contains('/')) {
+                return Err(invalid());
+            }
+            directives.push(directive);
+        }
+        if directives.is_empty() {
+            directives.push(Directive {
+                module: None,
+                level: FilterLevel::Error,
+            });
+        }
+        directives.sort_by_key(|d| d.module.as_ref().map_or(0, String::len));
+        Ok(LevelFilter { directives })
+    }
+}
+
+impl fmt::Display for LevelFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let directives = self
+            .directives
+            .iter()
+            .map(|d| {
+                let level = d.level.as_str().to_lowercase();
+                match &d.module {
+                    Some(module) => format!("{}={}", module, level),
+                    None => level,
+                }
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", directives.join(","))
+    }
+}
+
+/// Drops records not enabled by the `LevelFilter`, which can be replaced while logging
+pub(crate) struct FilterDrain<D> {
+    drain: D,
+    filter: SharedFilter,
+}
+
+impl<D> FilterDrain<D> {
+    pub(crate) fn new(drain: D, filter: SharedFilter) -> Self {
+        FilterDrain { drain, filter }
+    }
+}
+
+impl<D: Drain> Drain for FilterDrain<D> {
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        let enabled = self
+            .filter
+            .read()
+            .unwrap()
+            .enabled(record.level(), record.module());
+        if enabled {
+            self.drain.log(record, values).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_filter() {
+        let filter: LevelFilter = "info, hyper=warn, app::db=trace, app::db::pool=off"
+            .parse()
+            .unwrap();
+        assert!(filter.enabled(Level::Info, "app"));
+        assert!(!filter.enabled(Level::Debug, "app"));
+        assert!(!filter.enabled(Level::Info, "hyper::client"));
+        assert!(filter.enabled(Level::Trace, "app::db"));
+        assert!(!filter.enabled(Level::Error, "app::db::pool"));
+
+        let filter: LevelFilter = "app".parse().unwrap();
+        assert!(filter.enabled(Level::Trace, "app::db"));
+        assert!(!filter.enabled(Level::Critical, "hyper"));
+
+        let filter: LevelFilter = "".parse().unwrap();
+        assert!(filter.enabled(Level::Error, "app"));
+        assert!(!filter.enabled(Level::Warning, "app"));
+    }
+
+    #[test]
+    fn test_filter_display() {
+        let filter: LevelFilter = "app::db=trace, warning,hyper".parse().unwrap();
+        assert_eq!(filter.to_string(), "warning,hyper=trace,app::db=trace");
+        assert_eq!(filter.to_string().parse::<LevelFilter>().unwrap(), filter);
+        assert_eq!("".parse::<LevelFilter>().unwrap().to_string(), "error");
+    }
+
+    // restores the filter of the global `LOGGER` changed by a test
+    struct RestoreFilter(String);
+
+    impl Drop for RestoreFilter {
+        fn drop(&mut self) {
+            set_filter(&self.0).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_set_filter() {
+        let _restore = RestoreFilter(current_filter());
+        set_filter("info,app=trace").unwrap();
+        assert_eq!(current_filter(), "info,app=trace");
+        assert!(matches!(
+            set_filter("app=loud"),
+            Err(Error::InvalidFilter(_))
+        ));
+        assert_eq!(current_filter(), "info,app=trace");
+    }
+
+    #[test]
+    fn test_invalid_filter() {
+        for spec in ["app=loud", "=info", "a=b=c", "info/foo"] {
+            assert!(
+                matches!(spec.parse::<LevelFilter>(), Err(Error::InvalidFilter(s)) if s == spec),
+                "{}",
+                spec
+            );
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use crate::{Error, Fields};
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use slog::{o, Drain, FnValue, Key, OwnedKV, OwnedKVList, PushFnValue, Record, Serializer, KV};
+use std::cell::RefCell;
+use std::env;
+use std::fmt::{self, Write as _};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// version of the Elastic Common Schema the `ecs` format follows
+const ECS_VERSION: &str = "1.12.0";
+
+pub(crate) type Clock = fn() -> DateTime<FixedOffset>;
+
+pub(crate) type FormatDrain = Box<dyn Drain<Ok = (), Err = slog::Never> + Send>;
+
+/// Name and version of the service, set with `LoggerConfig::service_name` and `service_version`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Service {
+    pub(crate) name: Option<String>,
+    pub(crate) version: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Human readable lines, `plain`
+    PlainText,
+    /// JSON with `ts`, `lvl`, `loc`, `msg` and `v` keys, `json`
+    #[default]
+    Json,
+    /// `key=value` pairs with the same keys as JSON, `logfmt`
+    Logfmt,
+    /// JSON with Elastic Common Schema field names, e.g. `@timestamp` and `log.level`, `ecs`
+    Ecs,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Self::PlainText),
+            "json" => Ok(Self::Json),
+            "logfmt" => Ok(Self::Logfmt),
+            "ecs" => Ok(Self::Ecs),
+            "" => Ok(Default::default()),
+            _ => Err(Error::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+impl OutputFormat {
+    const ENV_NAME: &'static str = "RUST_LOG_FORMAT";
+
+    pub(crate) fn from_env() -> Result<Self, Error> {
+        env::var(Self::ENV_NAME).unwrap_or_default().parse()
+    }
+
+    /// Drain writing records in this format, `now` is the time of a record
+    pub(crate) fn drain<W: Write + Send + 'static>(
+        self,
+        writer: W,
+        now: Clock,
+        service: Service,
+    ) -> FormatDrain {
+        match self {
+            OutputFormat::PlainText => {
+                let decorator = slog_term::PlainDecorator::new(writer);
+                Box::new(slog_term::FullFormat::new(decorator).build().fuse())
+            }
+            // values are serialized in reverse order
+            OutputFormat::Json => Box::new(
+                slog_json::Json::new(writer)
+                    .add_key_value(o!(
+                        "ts" => PushFnValue(move |_: &Record, ser| {
+                            ser.emit(now().to_rfc3339())
+                        }),
+                        "lvl" => FnValue(move |rec: &Record| {
+                            rec.level().as_short_str()
+                        }),
+                        "loc" => FnValue(move |rec: &Record| {
+                            format!("{}:{}", rec.module(), rec.line())
+                        }),
+                        "msg" => PushFnValue(move |rec: &Record, ser| {
+                            ser.emit(rec.msg())
+                        }),
+                        "v" => VERSION,
+                    ))
+                    .build()
+                    .fuse(),
+            ),
+            OutputFormat::Logfmt => Box::new(
+                Logfmt {
+                    io: RefCell::new(writer),
+                    now,
+                }
+                .fuse(),
+            ),
+            OutputFormat::Ecs => Box::new(
+                slog_json::Json::new(writer)
+                    .add_key_value(o!(
+                        "ecs.version" => ECS_VERSION,
+                        "log.origin.file.line" => FnValue(move |rec: &Record| rec.line()),
+                        "log.origin.file.name" => FnValue(move |rec: &Record| rec.file()),
+                        "log.logger" => FnValue(move |rec: &Record| rec.module()),
+                        "message" => PushFnValue(move |rec: &Record, ser| {
+                            ser.emit(rec.msg())
+                        }),
+                        "log.level" => FnValue(move |rec: &Record| {
+                            ecs_level(rec.level())
+                        }),
+                        "@timestamp" => PushFnValue(move |_: &Record, ser| {
+                            let ts = now().with_timezone(&Utc);
+                            ser.emit(ts.to_rfc3339_opts(SecondsFormat::Millis, true))
+                        }),
+                    ))
+                    // only the fields that are set
+                    .add_key_value(OwnedKV(Fields(
+                        vec![
+                            ("service.name", service.name),
+                            ("service.version", service.version),
+                        ]
+                        .into_iter()
+                        .filter_map(|(key, value)| Some((key, value?)))
+                        .collect(),
+                    )))
+                    .build()
+                    .fuse(),
+            ),
+        }
+    }
+}
+
+pub(crate) fn local_now() -> DateTime<FixedOffset> {
+    chrono::Local::now().into()
+}
+
+fn ecs_level(level: slog::Level) -> &'static str {
+    match level {
+        slog::Level::Critical => "critical",
+        slog::Level::Error => "error",
+        slog::Level::Warning => "warn",
+        slog::Level::Info => "info",
+        slog::Level::Debug => "debug",
+        slog::Level::Trace => "trace",
+    }
+}
+
+/// Writes records as lines of `key=value` pairs,
+/// empty values and ones with spaces, quotes or `=` are quoted
+struct Logfmt<W: Write> {
+    io: RefCell<W>,
+    now: Clock,
+}
+
+impl<W: Write> Drain for Logfmt<W> {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
+        let mut line = LogfmtLine(String::new());
+        line.push("ts", &(self.now)().to_rfc3339());
+        line.push("lvl", record.level().as_short_str());
+        line.push("loc", &format!("{}:{}", record.module(), record.line()));
+        line.push("msg", &record.msg().to_string());
+        line.push("v", VERSION);
+        record
+            .kv()
+            .serialize(record, &mut line)
+            .and_then(|_| values.serialize(record, &mut line))?;
+        line.0.push('\n');
+        let mut io = self.io.borrow_mut();
+        io.write_all(line.0.as_bytes())?;
+        io.flush()
+    }
+}
+
+struct LogfmtLine(String);
+
+impl LogfmtLine {
+    fn push(&mut self, key: &str, value: &str) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        self.0.push_str(key);
+        self.0.push('=');
+        let quote = value.is_empty()
+            || value
+                .chars()
+                .any(|c| c.is_whitespace() || c == '"' || c == '=' || c == '\\');
+        if !quote {
+            self.0.push_str(value);
+            return;
+        }
+        self.0.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => self.0.push_str("\\\""),
+                '\\' => self.0.push_str("\\\\"),
+                '\n' => self.0.push_str("\\n"),
+                '\r' => self.0.push_str("\\r"),
+                '\t' => self.0.push_str("\\t"),
+                c => self.0.push(c),
+            }
+        }
+        self.0.push('"');
+    }
+}
+
+impl Serializer for LogfmtLine {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        let mut value = String::new();
+        value.write_fmt(*val)?;
+        self.push(key, &value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use slog::{b, Level, Logger, RecordLocation, RecordStatic};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn fixed_now() -> DateTime<FixedOffset> {
+        let ts = FixedOffset::east_opt(3 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2022, 3, 14, 15, 9, 26)
+            .unwrap();
+        ts + chrono::Duration::milliseconds(535)
+    }
+
+    static LOCATION: RecordLocation = RecordLocation {
+        file: "src/assets.rs",
+        line: 42,
+        column: 9,
+        function: "",
+        module: "service::assets",
+    };
+
+    // a record with fields, logged with a context field
+    fn format(format: OutputFormat) -> String {
+        format_with_service(format, Service::default())
+    }
+
+    fn format_with_service(format: OutputFormat, service: Service) -> String {
+        let buffer = Buffer::default();
+        let logger = Logger::root(
+            Mutex::new(format.drain(buffer.clone(), fixed_now, service)).fuse(),
+            o!(),
+        );
+        let logger = logger.new(o!("req_id" => "abc"));
+        let rs = RecordStatic {
+            location: &LOCATION,
+            tag: "",
+            level: Level::Warning,
+        };
+        let args = format_args!("asset \"{}\" not found", "WAVES");
+        let kv = b!("height" => 3000, "node" => "main node");
+        logger.log(&Record::new(&rs, &args, kv));
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            format(OutputFormat::Json),
+            format!(
+                concat!(
+                    r#"{{"v":"{}","msg":"asset \"WAVES\" not found","loc":"service::assets:42","#,
+                    r#""lvl":"WARN","ts":"2022-03-14T15:09:26.535+03:00","req_id":"abc","#,
+                    r#""node":"main node","height":3000}}"#,
+                    "\n"
+                ),
+                VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn test_logfmt() {
+        assert_eq!(
+            format(OutputFormat::Logfmt),
+            format!(
+                concat!(
+                    r#"ts=2022-03-14T15:09:26.535+03:00 lvl=WARN loc=service::assets:42 "#,
+                    r#"msg="asset \"WAVES\" not found" v={} node="main node" height=3000 req_id=abc"#,
+                    "\n"
+                ),
+                VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn test_ecs() {
+        let service = Service {
+            name: Some("assets-service".to_string()),
+            version: Some("1.4.2".to_string()),
+        };
+        assert_eq!(
+            format_with_service(OutputFormat::Ecs, service),
+            format!(
+                concat!(
+                    r#"{{"@timestamp":"2022-03-14T12:09:26.535Z","log.level":"warn","#,
+                    r#""message":"asset \"WAVES\" not found","log.logger":"service::assets","#,
+                    r#""log.origin.file.name":"src/assets.rs","log.origin.file.line":42,"#,
+                    r#""ecs.version":"{}","service.name":"assets-service","#,
+                    r#""service.version":"1.4.2","req_id":"abc","#,
+                    r#""node":"main node","height":3000}}"#,
+                    "\n"
+                ),
+                ECS_VERSION
+            )
+        );
+        // service fields are omitted unless configured
+        assert!(!format(OutputFormat::Ecs).contains("service."));
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(
+            "logfmt".parse::<OutputFormat>().unwrap(),
+            OutputFormat::Logfmt
+        );
+        assert_eq!("ecs".parse::<OutputFormat>().unwrap(), OutputFormat::Ecs);
+        assert_eq!("".parse::<OutputFormat>().unwrap(), OutputFormat::Json);
+    }
+}
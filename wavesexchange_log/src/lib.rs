@@ -1,124 +1,154 @@
 pub use ::slog;
 
-use crate::format::OutputFormat;
-use once_cell::sync::Lazy;
-use slog::{o, Drain, FnValue, Logger, PushFnValue, Record};
-use std::sync::Mutex;
-
-pub static LOGGER: Lazy<slog::Logger> = Lazy::new(|| init_logger());
-
-fn init_logger() -> Logger {
-    match OutputFormat::from_env() {
-        OutputFormat::PlainText => {
-            let decorator = slog_term::PlainDecorator::new(std::io::stdout());
-            let drain = slog_term::FullFormat::new(decorator).build().fuse();
-            let drain = slog_async::Async::new(drain).chan_size(1000).build().fuse();
-            let drain = slog_envlogger::new(drain).fuse();
-            let drain = Mutex::new(drain).map(slog::Fuse);
-            slog::Logger::root(drain, o!())
-        }
-        OutputFormat::Json => {
-            let drain = slog_json::Json::new(std::io::stdout()).build().fuse();
-            let drain = slog_async::Async::new(drain).chan_size(1000).build().fuse();
-            let drain = slog_envlogger::new(drain).fuse();
-            let drain = Mutex::new(drain).map(slog::Fuse);
-            slog::Logger::root(
-                drain,
-                o!(
-                    "ts" => PushFnValue(move |_: &Record, ser| {
-                        ser.emit(chrono::Local::now().to_rfc3339())
-                    }),
-                    "lvl" => FnValue(move |rec: &Record| {
-                        rec.level().as_short_str()
-                    }),
-                    "loc" => FnValue(move |rec: &Record| {
-                        format!("{}:{}", rec.module(), rec.line())
-                    }),
-                    "msg" => PushFnValue(move |rec: &Record, ser| {
-                        ser.emit(rec.msg())
-                    }),
-                    "v" => env!("CARGO_PKG_VERSION"),
-                ),
-            )
-        }
+pub use crate::config::{LoggerConfig, Output};
+pub use crate::context::{current_span, logger, LogContext};
+pub use crate::filter::{current_filter, set_filter};
+pub use crate::format::OutputFormat;
+pub use crate::queue::{dropped_records, flush, flush_guard, FlushGuard, Overflow};
+pub use crate::rate_limit::RateLimit;
+pub use crate::trace::{in_span, SpanContext};
+
+use crate::filter::SharedFilter;
+use crate::queue::Queue;
+use once_cell::sync::{Lazy, OnceCell};
+use slog::{Logger, Record, Serializer, KV};
+
+mod config;
+mod context;
+mod filter;
+mod format;
+mod queue;
+mod rate_limit;
+mod redact;
+mod trace;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+/// Global root logger, the logging macros log to it with fields of the current `LogContext`.
+///
+/// Configured with `LoggerConfig::init`, or from the environment on first use if it wasn't called.
+pub static LOGGER: Lazy<Logger> = Lazy::new(|| root().logger.clone());
+
+static ROOT: OnceCell<Root> = OnceCell::new();
+
+pub(crate) struct Root {
+    logger: Logger,
+    filter: SharedFilter,
+    queue: std::sync::Arc<Queue>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Unrecognized RUST_LOG_FORMAT value: '{0}'")]
+    UnknownFormat(String),
+    #[error("Invalid log filter: '{0}'")]
+    InvalidFilter(String),
+    #[error("Can't open log output: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Logger is already initialized")]
+    AlreadyInitialized,
+}
+
+// string fields of a config or a context
+pub(crate) struct Fields(Vec<(&'static str, String)>);
+
+impl KV for Fields {
+    fn serialize(&self, _: &Record, ser: &mut dyn Serializer) -> slog::Result {
+        self.0
+            .iter()
+            .try_for_each(|(key, value)| ser.emit_str(key, value))
     }
 }
 
+pub(crate) fn root() -> &'static Root {
+    ROOT.get_or_init(|| {
+        LoggerConfig::from_env()
+            .and_then(LoggerConfig::build_root)
+            .unwrap_or_else(|e| {
+                eprintln!("{}, falling back to the default logger config", e);
+                LoggerConfig::new()
+                    .build_root()
+                    .expect("default logger config is valid")
+            })
+    })
+}
+
 #[macro_export]
 macro_rules! trace(
     ($arg:literal) => {
-        $crate::slog::trace!($crate::LOGGER, "{}", $arg)
+        $crate::slog::trace!($crate::logger(), "{}", $arg)
     };
     ($tag:expr, $($args:tt)*) => {
-        $crate::slog::trace!($crate::LOGGER, $tag, $($args)*)
+        $crate::slog::trace!($crate::logger(), $tag, $($args)*)
     };
     ($($args:tt)*) => {
-        $crate::slog::trace!($crate::LOGGER, "{:?}", $($args)*)
+        $crate::slog::trace!($crate::logger(), "{:?}", $($args)*)
     };
 );
 
 #[macro_export]
 macro_rules! debug(
     ($arg:literal) => {
-        $crate::slog::debug!($crate::LOGGER, "{}", $arg)
+        $crate::slog::debug!($crate::logger(), "{}", $arg)
     };
     ($tag:expr, $($args:tt)*) => {
-        $crate::slog::debug!($crate::LOGGER, $tag, $($args)*)
+        $crate::slog::debug!($crate::logger(), $tag, $($args)*)
     };
     ($($args:tt)*) => {
-        $crate::slog::debug!($crate::LOGGER, "{:?}", $($args)*)
+        $crate::slog::debug!($crate::logger(), "{:?}", $($args)*)
     };
 );
 
 #[macro_export]
 macro_rules! info(
     ($arg:literal) => {
-        $crate::slog::info!($crate::LOGGER, "{}", $arg)
+        $crate::slog::info!($crate::logger(), "{}", $arg)
     };
     ($tag:expr, $($args:tt)*) => {
-        $crate::slog::info!($crate::LOGGER, $tag, $($args)*)
+        $crate::slog::info!($crate::logger(), $tag, $($args)*)
     };
     ($($args:tt)*) => {
-        $crate::slog::info!($crate::LOGGER, "{:?}", $($args)*)
+        $crate::slog::info!($crate::logger(), "{:?}", $($args)*)
     };
 );
 
 #[macro_export]
 macro_rules! warn(
     ($arg:literal) => {
-        $crate::slog::warn!($crate::LOGGER, "{}", $arg)
+        $crate::slog::warn!($crate::logger(), "{}", $arg)
     };
     ($tag:expr, $($args:tt)*) => {
-        $crate::slog::warn!($crate::LOGGER, $tag, $($args)*)
+        $crate::slog::warn!($crate::logger(), $tag, $($args)*)
     };
     ($($args:tt)*) => {
-        $crate::slog::warn!($crate::LOGGER, "{:?}", $($args)*)
+        $crate::slog::warn!($crate::logger(), "{:?}", $($args)*)
     };
 );
 
 #[macro_export]
 macro_rules! error(
     ($arg:literal) => {
-        $crate::slog::error!($crate::LOGGER, "{}", $arg)
+        $crate::slog::error!($crate::logger(), "{}", $arg)
     };
     ($tag:expr, $($args:tt)*) => {
-        $crate::slog::error!($crate::LOGGER, $tag, $($args)*)
+        $crate::slog::error!($crate::logger(), $tag, $($args)*)
     };
     ($($args:tt)*) => {
-        $crate::slog::error!($crate::LOGGER, "{:?}", $($args)*)
+        $crate::slog::error!($crate::logger(), "{:?}", $($args)*)
     };
 );
 
 #[macro_export]
 macro_rules! crit(
     ($arg:literal) => {
-        $crate::slog::crit!($crate::LOGGER, "{}", $arg)
+        $crate::slog::crit!($crate::logger(), "{}", $arg)
     };
     ($tag:expr, $($args:tt)*) => {
-        $crate::slog::crit!($crate::LOGGER, $tag, $($args)*)
+        $crate::slog::crit!($crate::logger(), $tag, $($args)*)
     };
     ($($args:tt)*) => {
-        $crate::slog::crit!($crate::LOGGER, "{:?}", $($args)*)
+        $crate::slog::crit!($crate::logger(), "{:?}", $($args)*)
     };
 );
 
@@ -133,10 +163,13 @@ macro_rules! crit(
 /// } // At the end of the scope the execution time is logged.
 /// ```
 ///
+/// The elapsed time is logged in the `elapsed_ms` field, and the name in the `timer` field.
+/// The name can be any expression converting into `Cow<'static, str>`, e.g. `format!("load {}", id)`.
+///
 /// When not specified, logging level `debug` is used by default,
 /// as in the example above.
 ///
-/// Logging level can be set explicitly, either `trace`, `debug` or `info`:
+/// Logging level can be set explicitly, one of `trace`, `debug`, `info`, `warn`, `error` or `crit`:
 /// ```no_run
 /// # use wavesexchange_log::timer;
 /// timer!("this is a test", level = info);
@@ -159,122 +192,249 @@ macro_rules! crit(
 /// If logging level is not specified, `trace` will be used by default
 /// for verbose mode.
 ///
-/// If both logging level and verbose mode are set,
-/// the logging level must come first:
+/// With a slow threshold in milliseconds the finish is logged at `warn`
+/// if it took longer, and at the usual level otherwise:
 ///
 /// ```no_run
 /// # use wavesexchange_log::timer;
-/// timer!("this is a test", level = info, verbose);
-/// timer!("this is a test", level = debug, verbose);
-/// timer!("this is a test", level = trace, verbose);
+/// # let threshold_ms = 100;
+/// timer!("this is a test", level = debug, slow = 500);
+/// timer!("this is a test", slow = threshold_ms);
 /// ```
+///
+/// Extra key-values, added to every record of the timer, follow a semicolon.
+/// Values are evaluated when the timer starts and must be owned:
+///
+/// ```no_run
+/// # use wavesexchange_log::timer;
+/// # let ids = vec![1, 2];
+/// timer!(format!("load {} assets", ids.len()), level = info; "count" => ids.len(), "source" => "node");
+/// ```
+///
+/// To time a future use [`timed!`](crate::timed) with the same options.
 #[macro_export]
 macro_rules! timer {
-    ($name:literal) => {
-        $crate::timer!($name, level = debug)
+    (@opt $timer:ident, level = trace) => {
+        $timer.level($crate::slog::Level::Trace)
     };
-    ($name:literal, verbose) => {
-        $crate::timer!($name, level = trace, verbose)
+    (@opt $timer:ident, level = debug) => {
+        $timer.level($crate::slog::Level::Debug)
     };
-    ($name:literal, level = trace) => {
-        $crate::timer!(@ $name, $crate::slog::Level::Trace, false)
+    (@opt $timer:ident, level = info) => {
+        $timer.level($crate::slog::Level::Info)
     };
-    ($name:literal, level = debug) => {
-        $crate::timer!(@ $name, $crate::slog::Level::Debug, false)
+    (@opt $timer:ident, level = warn) => {
+        $timer.level($crate::slog::Level::Warning)
     };
-    ($name:literal, level = info) => {
-        $crate::timer!(@ $name, $crate::slog::Level::Info, false)
+    (@opt $timer:ident, level = error) => {
+        $timer.level($crate::slog::Level::Error)
     };
-    ($name:literal, level = trace, verbose) => {
-        $crate::timer!(@ $name, $crate::slog::Level::Trace, true)
+    (@opt $timer:ident, level = crit) => {
+        $timer.level($crate::slog::Level::Critical)
     };
-    ($name:literal, level = debug, verbose) => {
-        $crate::timer!(@ $name, $crate::slog::Level::Debug, true)
+    (@opt $timer:ident, verbose) => {
+        $timer.verbose()
     };
-    ($name:literal, level = info, verbose) => {
-        $crate::timer!(@ $name, $crate::slog::Level::Info, true)
+    (@opt $timer:ident, slow = $ms:tt) => {
+        $timer.slow(::std::time::Duration::from_millis($ms))
     };
-    (@ $name:literal, $level:expr, $verbose:literal) => {
-        let _timer = $crate::scopetimer::ScopeTimer::new($name, $level, $verbose);
+    (@new $name:expr $(, $opt:ident $(= $val:tt)?)* $(; $($key:expr => $value:expr),+)?) => {{
+        let timer = $crate::scopetimer::ScopeTimer::new($name);
+        $(let timer = $crate::timer!(@opt timer, $opt $(= $val)?);)*
+        $(let timer = timer.with_values($crate::slog::o!($($key => $value),+));)?
+        timer
+    }};
+    ($name:expr $(, $opt:ident $(= $val:tt)?)* $(; $($key:expr => $value:expr),+ $(,)?)?) => {
+        let _timer = $crate::timer!(@new $name $(, $opt $(= $val)?)* $(; $($key => $value),+)?).start();
+    };
+}
+
+/// Wraps a future into a timer, which logs how long it took to complete.
+///
+/// Takes the same options as [`timer!`](crate::timer) after the future:
+///
+/// ```no_run
+/// # use wavesexchange_log::timed;
+/// # async fn load_assets() {}
+/// # async fn f() {
+/// timed!(load_assets(), "load assets", level = info, slow = 500).await;
+/// # }
+/// ```
+///
+/// The timer starts on the first poll, and logs when the future completes or is dropped.
+#[macro_export]
+macro_rules! timed {
+    ($fut:expr, $name:expr $(, $opt:ident $(= $val:tt)?)* $(; $($key:expr => $value:expr),+ $(,)?)?) => {
+        $crate::timer!(@new $name $(, $opt $(= $val)?)* $(; $($key => $value),+)?).time($fut)
     };
 }
 
 pub mod scopetimer {
-    use slog::Level;
-    use std::{fmt, time};
+    use slog::{Level, Logger, OwnedKV, SendSyncRefUnwindSafeKV, KV};
+    use std::borrow::Cow;
+    use std::fmt;
+    use std::future::Future;
+    use std::time::{Duration, Instant};
 
-    pub struct ScopeTimer(&'static str, Level, bool, time::Instant);
+    /// Timer logging the time elapsed until it's dropped, usually created with `timer!` or `timed!`
+    pub struct ScopeTimer {
+        name: Cow<'static, str>,
+        level: Option<Level>,
+        verbose: bool,
+        slow: Option<Duration>,
+        logger: Logger,
+        started: Instant,
+    }
 
     impl ScopeTimer {
-        #[inline(always)]
-        pub fn new(name: &'static str, level: Level, verbose: bool) -> Self {
-            if verbose {
-                print(level, format_args!("BEGIN {}", name));
+        pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+            ScopeTimer {
+                name: name.into(),
+                level: None,
+                verbose: false,
+                slow: None,
+                logger: crate::logger(),
+                started: Instant::now(),
+            }
+        }
+
+        pub fn level(mut self, level: Level) -> Self {
+            self.level = Some(level);
+            self
+        }
+
+        /// Log the start as well, at `trace` level by default
+        pub fn verbose(mut self) -> Self {
+            self.verbose = true;
+            self
+        }
+
+        /// Log the finish at `warn` if it took longer than `threshold`
+        pub fn slow(mut self, threshold: Duration) -> Self {
+            self.slow = Some(threshold);
+            self
+        }
+
+        pub fn with_values<T>(mut self, values: OwnedKV<T>) -> Self
+        where
+            T: SendSyncRefUnwindSafeKV + 'static,
+        {
+            self.logger = self.logger.new(values);
+            self
+        }
+
+        /// Start timing
+        pub fn start(mut self) -> Self {
+            if self.verbose {
+                let level = self.default_level();
+                self.print(level, format_args!("BEGIN {}", self.name), None);
+            }
+            self.started = Instant::now();
+            self
+        }
+
+        /// Time `fut` until it completes
+        pub async fn time<F: Future>(self, fut: F) -> F::Output {
+            let _timer = self.start();
+            fut.await
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            self.started.elapsed()
+        }
+
+        fn default_level(&self) -> Level {
+            match (self.level, self.verbose) {
+                (Some(level), _) => level,
+                (None, true) => Level::Trace,
+                (None, false) => Level::Debug,
+            }
+        }
+
+        fn end_level(&self, elapsed: Duration) -> Level {
+            match self.slow {
+                Some(threshold) if elapsed > threshold => Level::Warning,
+                _ => self.default_level(),
+            }
+        }
+
+        fn print(&self, level: Level, msg: fmt::Arguments, elapsed_ms: Option<f64>) {
+            let kv = Fields {
+                name: &self.name,
+                elapsed_ms,
+            };
+            let logger = &self.logger;
+            match level {
+                Level::Trace => slog::trace!(logger, "{}", msg; kv),
+                Level::Debug => slog::debug!(logger, "{}", msg; kv),
+                Level::Info => slog::info!(logger, "{}", msg; kv),
+                Level::Warning => slog::warn!(logger, "{}", msg; kv),
+                Level::Error => slog::error!(logger, "{}", msg; kv),
+                Level::Critical => slog::crit!(logger, "{}", msg; kv),
             }
-            ScopeTimer(name, level, verbose, time::Instant::now())
         }
     }
 
     impl Drop for ScopeTimer {
-        #[inline(always)]
         fn drop(&mut self) {
-            let &mut ScopeTimer(name, level, verbose, ref started) = self;
-            let elapsed = started.elapsed();
+            let elapsed = self.started.elapsed();
             const MS_IN_SEC: f64 = 1_000.0;
             let elapsed_ms = elapsed.as_secs_f64() * MS_IN_SEC;
-            if verbose {
-                print(
-                    level,
-                    format_args!("END   {}: elapsed {}ms", name, elapsed_ms),
-                );
+            let level = self.end_level(elapsed);
+            if self.verbose {
+                let msg = format_args!("END   {}: elapsed {}ms", self.name, elapsed_ms);
+                self.print(level, msg, Some(elapsed_ms));
             } else {
-                print(level, format_args!("{}: completed in {}ms", name, elapsed_ms));
+                let msg = format_args!("{}: completed in {}ms", self.name, elapsed_ms);
+                self.print(level, msg, Some(elapsed_ms));
             }
         }
     }
 
-    #[inline(always)]
-    fn print(level: Level, msg: fmt::Arguments) {
-        match level {
-            Level::Trace => super::trace!("{}", msg),
-            Level::Debug => super::debug!("{}", msg),
-            Level::Info => super::info!("{}", msg),
-            _ => panic!("Bad log level for scope timer"),
+    struct Fields<'a> {
+        name: &'a str,
+        elapsed_ms: Option<f64>,
+    }
+
+    impl KV for Fields<'_> {
+        fn serialize(&self, _: &slog::Record, ser: &mut dyn slog::Serializer) -> slog::Result {
+            ser.emit_str("timer", self.name)?;
+            match self.elapsed_ms {
+                Some(elapsed_ms) => ser.emit_f64("elapsed_ms", elapsed_ms),
+                None => Ok(()),
+            }
         }
     }
-}
 
-mod format {
-    use std::env;
+    #[cfg(test)]
+    mod tests {
+        use super::*;
 
-    #[derive(Copy, Clone)]
-    pub(crate) enum OutputFormat {
-        PlainText,
-        Json,
-    }
+        #[test]
+        fn test_timer_level() {
+            let timer = ScopeTimer::new("test");
+            assert_eq!(timer.end_level(Duration::from_secs(1)), Level::Debug);
+            let timer = timer.verbose();
+            assert_eq!(timer.end_level(Duration::from_secs(1)), Level::Trace);
 
-    impl Default for OutputFormat {
-        fn default() -> Self {
-            Self::Json
+            let timer = ScopeTimer::new(format!("test {}", 1))
+                .level(Level::Info)
+                .slow(Duration::from_millis(100));
+            assert_eq!(timer.end_level(Duration::from_millis(100)), Level::Info);
+            assert_eq!(timer.end_level(Duration::from_millis(101)), Level::Warning);
         }
-    }
 
-    impl<S: AsRef<str>> From<S> for OutputFormat {
-        fn from(s: S) -> Self {
-            match s.as_ref() {
-                "plain" => Self::PlainText,
-                "json" => Self::Json,
-                "" => Default::default(),
-                _ => panic!("Unrecognized {} value: '{}'", Self::ENV_NAME, s.as_ref()),
+        #[tokio::test]
+        async fn test_timer_macros() {
+            let id = 1;
+            {
+                crate::timer!("test");
+                crate::timer!("test", level = info, verbose);
+                crate::timer!(format!("test {}", id), level = crit, slow = 10; "id" => id);
+                crate::timer!("test", verbose, slow = 10; "id" => id, "name" => "x",);
             }
-        }
-    }
-
-    impl OutputFormat {
-        const ENV_NAME: &'static str = "RUST_LOG_FORMAT";
-
-        pub(crate) fn from_env() -> Self {
-            Self::from(env::var(Self::ENV_NAME).ok().unwrap_or_default())
+            let value = crate::timed!(async { id + 1 }, "test", level = warn; "id" => id).await;
+            assert_eq!(value, 2);
         }
     }
 }
@@ -0,0 +1,373 @@
+use crate::root;
+use slog::{b, record, Drain, Level, OwnedKVList};
+use slog_async::AsyncRecord;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// how often the number of dropped records is logged while the queue stays busy
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What happens to a record when the queue to the logging thread is full,
+/// set with `LoggerConfig::overflow`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Overflow {
+    /// Wait until the logging thread takes a record, slowing the caller down
+    Block,
+    /// Drop the record being logged
+    #[default]
+    DropNewest,
+    /// Drop the oldest queued record to make room for the one being logged
+    DropOldest,
+}
+
+/// Number of records of the global `LOGGER` dropped because its queue was full
+pub fn dropped_records() -> u64 {
+    root().queue.dropped.load(Ordering::Relaxed)
+}
+
+/// Wait until records logged so far to the global `LOGGER` are written
+pub fn flush() {
+    root().queue.flush()
+}
+
+/// Flushes the global `LOGGER` when dropped, so that final records aren't lost on shutdown.
+///
+/// ```no_run
+/// # use wavesexchange_log::{flush_guard, info};
+/// fn main() {
+///     let _guard = flush_guard();
+///     info!("shutting down");
+/// } // written before the process exits
+/// ```
+#[must_use = "the logger is flushed when the guard is dropped"]
+pub struct FlushGuard(());
+
+pub fn flush_guard() -> FlushGuard {
+    FlushGuard(())
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        flush()
+    }
+}
+
+pub(crate) struct Queue {
+    state: Mutex<State>,
+    capacity: usize,
+    overflow: Overflow,
+    // a record was queued or the drain was closed
+    pushed: Condvar,
+    // a record was taken or written, or the logging thread exited
+    popped: Condvar,
+    dropped: AtomicU64,
+}
+
+struct State {
+    records: VecDeque<AsyncRecord>,
+    // number of records ever queued, and written or dropped from the queue
+    queued: u64,
+    done: u64,
+    unreported: u64,
+    last_report: Instant,
+    // number of callers waiting for room in the queue
+    blocked: usize,
+    closed: bool,
+    exited: bool,
+}
+
+impl Queue {
+    fn flush(&self) {
+        let state = self.state.lock().unwrap();
+        let queued = state.queued;
+        drop(
+            self.popped
+                .wait_while(state, |s| s.done < queued && !s.exited)
+                .unwrap(),
+        );
+    }
+
+    fn count_dropped(&self, state: &mut State) {
+        state.unreported += 1;
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Writes records to `drain` on a separate thread through a queue of `capacity` records.
+///
+/// Records dropped on overflow are counted, and their number is logged as
+/// "dropped N log records" with the static `values` of the root logger
+/// once the queue is emptied, or every 10 seconds while it's busy.
+/// Records still queued are written when the drain is dropped.
+pub(crate) struct QueueDrain {
+    queue: Arc<Queue>,
+    // in a mutex to be unwind safe
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl QueueDrain {
+    pub(crate) fn new<D>(drain: D, capacity: usize, overflow: Overflow, values: OwnedKVList) -> Self
+    where
+        D: Drain<Ok = (), Err = slog::Never> + Send + 'static,
+    {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(State {
+                records: VecDeque::new(),
+                queued: 0,
+                done: 0,
+                unreported: 0,
+                last_report: Instant::now(),
+                blocked: 0,
+                closed: false,
+                exited: false,
+            }),
+            capacity: capacity.max(1),
+            overflow,
+            pushed: Condvar::new(),
+            popped: Condvar::new(),
+            dropped: AtomicU64::new(0),
+        });
+        let worker = {
+            let queue = queue.clone();
+            thread::Builder::new()
+                .name("slog-queue".to_string())
+                .spawn(move || run(&queue, drain, values))
+                .expect("failed to spawn the logging thread")
+        };
+        QueueDrain {
+            queue,
+            worker: Mutex::new(Some(worker)),
+        }
+    }
+
+    pub(crate) fn queue(&self) -> Arc<Queue> {
+        self.queue.clone()
+    }
+}
+
+impl Drain for QueueDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &slog::Record, values: &OwnedKVList) -> Result<(), slog::Never> {
+        let queue = &*self.queue;
+        let record = AsyncRecord::from(record, values);
+        let mut state = queue.state.lock().unwrap();
+        if state.records.len() >= queue.capacity {
+            match queue.overflow {
+                Overflow::Block => {
+                    state.blocked += 1;
+                    state = queue
+                        .popped
+                        .wait_while(state, |s| s.records.len() >= queue.capacity && !s.exited)
+                        .unwrap();
+                    state.blocked -= 1;
+                    if state.exited {
+                        queue.count_dropped(&mut state);
+                        return Ok(());
+                    }
+                }
+                Overflow::DropNewest => {
+                    queue.count_dropped(&mut state);
+                    return Ok(());
+                }
+                Overflow::DropOldest => {
+                    state.records.pop_front();
+                    state.done += 1;
+                    queue.count_dropped(&mut state);
+                }
+            }
+        }
+        state.records.push_back(record);
+        state.queued += 1;
+        queue.pushed.notify_one();
+        Ok(())
+    }
+}
+
+impl Drop for QueueDrain {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().closed = true;
+        self.queue.pushed.notify_one();
+        if let Some(worker) = self.worker.lock().unwrap().take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+// wakes up callers waiting for the logging thread, even if the drain panicked
+struct Exit<'a>(&'a Queue);
+
+impl Drop for Exit<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.0.state.lock() {
+            state.exited = true;
+        }
+        self.0.popped.notify_all();
+    }
+}
+
+fn run<D: Drain<Ok = (), Err = slog::Never>>(queue: &Queue, drain: D, values: OwnedKVList) {
+    let _exit = Exit(queue);
+    loop {
+        let (record, dropped) = {
+            let state = queue.state.lock().unwrap();
+            let mut state = queue
+                .pushed
+                .wait_while(state, |s| s.records.is_empty() && !s.closed)
+                .unwrap();
+            let record = match state.records.pop_front() {
+                Some(record) => record,
+                None => return,
+            };
+            let report = state.unreported > 0
+                && (state.records.is_empty() || state.last_report.elapsed() >= REPORT_INTERVAL);
+            let dropped = if report {
+                state.last_report = Instant::now();
+                std::mem::take(&mut state.unreported)
+            } else {
+                0
+            };
+            (record, dropped)
+        };
+        queue.popped.notify_all();
+        if dropped > 0 {
+            let args = format_args!(
+                "dropped {} log records, the logging queue was full",
+                dropped
+            );
+            let kv = b!("dropped" => dropped);
+            // without the context of the next record, which has nothing to do with the dropped ones
+            let _ = drain.log(&record!(Level::Warning, "", &args, kv), &values);
+        }
+        record.as_record_values(|record, values| {
+            let _ = drain.log(record, values);
+        });
+        queue.state.lock().unwrap().done += 1;
+        queue.popped.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::{info, o, Logger, Record};
+    use std::sync::mpsc;
+
+    // writes messages once the test lets it, a record per message sent to the gate
+    struct Gated {
+        written: Arc<Mutex<Vec<String>>>,
+        gate: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl Drain for Gated {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &Record, _: &OwnedKVList) -> Result<(), slog::Never> {
+            let _ = self.gate.lock().unwrap().recv();
+            self.written.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    // logs 5 records to a queue of 2, while the first one is being written
+    fn overflow(overflow: Overflow) -> (Vec<String>, u64) {
+        let written = Arc::new(Mutex::new(vec![]));
+        let (open, gate) = mpsc::channel();
+        let drain = Gated {
+            written: written.clone(),
+            gate: Mutex::new(gate),
+        };
+        let drain = QueueDrain::new(drain, 2, overflow, o!().into());
+        let queue = drain.queue();
+        let logger = Logger::root(drain, o!());
+
+        info!(logger, "1");
+        while !queue.state.lock().unwrap().records.is_empty() {
+            thread::yield_now();
+        }
+        let rest = {
+            let logger = logger.clone();
+            thread::spawn(move || {
+                for msg in ["2", "3", "4", "5"] {
+                    info!(logger, "{}", msg);
+                }
+            })
+        };
+        if overflow != Overflow::Block {
+            rest.join().unwrap();
+            drop(open);
+        } else {
+            // the caller waits for the first record to be written
+            while queue.state.lock().unwrap().blocked == 0 {
+                thread::yield_now();
+            }
+            assert_eq!(queue.state.lock().unwrap().records.len(), 2);
+            assert!(!rest.is_finished());
+            drop(open);
+            rest.join().unwrap();
+        }
+        queue.flush();
+        let written = written.lock().unwrap().clone();
+        (written, queue.dropped.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn test_overflow() {
+        let dropped = "dropped 2 log records, the logging queue was full";
+        assert_eq!(
+            overflow(Overflow::DropNewest),
+            (
+                vec!["1", "2", dropped, "3"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                2
+            )
+        );
+        assert_eq!(
+            overflow(Overflow::DropOldest),
+            (
+                vec!["1", "4", dropped, "5"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                2
+            )
+        );
+        assert_eq!(
+            overflow(Overflow::Block),
+            (
+                vec!["1", "2", "3", "4", "5"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                0
+            )
+        );
+    }
+
+    #[test]
+    fn test_flush_on_drop() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let (open, gate) = mpsc::channel();
+        drop(open);
+        let drain = Gated {
+            written: written.clone(),
+            gate: Mutex::new(gate),
+        };
+        let logger = Logger::root(
+            QueueDrain::new(drain, 10, Overflow::Block, o!().into()),
+            o!(),
+        );
+        for i in 0..5 {
+            info!(logger, "{}", i);
+        }
+        drop(logger);
+        assert_eq!(written.lock().unwrap().len(), 5);
+    }
+}
@@ -0,0 +1,363 @@
+use slog::{kv, BorrowedKV, Drain, Level, OwnedKVList, Record, RecordLocation, RecordStatic};
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Limit of records logged by one call site, set with `LoggerConfig::rate_limit`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub interval: Duration,
+}
+
+// module, line and column of a logging macro call, which has a single message template
+type CallSite = (&'static str, u32, u32);
+
+struct Window {
+    started: Instant,
+    logged: u32,
+    suppressed: u64,
+    // of the first record, to report suppressed ones
+    location: RecordLocation,
+    level: Level,
+    tag: String,
+}
+
+struct Windows {
+    sites: HashMap<CallSite, Window>,
+    // end of the earliest interval with suppressed records
+    next_report: Option<Instant>,
+    closed: bool,
+}
+
+impl Windows {
+    // removes windows ended by `now`, and returns the ones with suppressed records
+    fn take_ended(&mut self, limit: &RateLimit, now: Instant) -> Vec<Window> {
+        let mut ended = vec![];
+        if self.next_report.is_none_or(|at| at > now) {
+            return ended;
+        }
+        for (site, window) in std::mem::take(&mut self.sites) {
+            if now.duration_since(window.started) < limit.interval {
+                self.sites.insert(site, window);
+            } else if window.suppressed > 0 {
+                ended.push(window);
+            }
+        }
+        ended.sort_by_key(|w| w.started);
+        self.next_report = self
+            .sites
+            .values()
+            .filter(|w| w.suppressed > 0)
+            .map(|w| w.started + limit.interval)
+            .min();
+        ended
+    }
+}
+
+struct Shared<D> {
+    drain: D,
+    limit: Option<RateLimit>,
+    // static values of the root logger
+    values: OwnedKVList,
+    windows: Mutex<Windows>,
+    // the next report is due earlier, or the drain was dropped
+    changed: Condvar,
+}
+
+/// Logs at most `burst` records of every call site per `interval`, and drops the rest.
+///
+/// The number of dropped records is logged as "suppressed N similar messages"
+/// with the location of the call site and the static `values` of the root logger,
+/// by the first record logged after its interval ends, or by a separate thread
+/// if no records are logged by then. Summaries still pending are logged when the drain is dropped.
+pub(crate) struct RateLimitDrain<D: Drain> {
+    shared: Arc<Shared<D>>,
+    // in a mutex to be unwind safe
+    reporter: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl<D> RateLimitDrain<D>
+where
+    D: Drain + Send + Sync + 'static,
+{
+    pub(crate) fn new(drain: D, limit: Option<RateLimit>, values: OwnedKVList) -> Self {
+        let shared = Arc::new(Shared {
+            drain,
+            limit,
+            values,
+            windows: Mutex::new(Windows {
+                sites: HashMap::new(),
+                next_report: None,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let reporter = limit.map(|limit| {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("slog-rate-limit".to_string())
+                .spawn(move || report(&shared, &limit))
+                .expect("failed to spawn the rate limit thread")
+        });
+        RateLimitDrain {
+            shared,
+            reporter: Mutex::new(reporter),
+        }
+    }
+}
+
+impl<D: Drain> Shared<D> {
+    // whether to log the record, and windows ended with suppressed records
+    fn admit(&self, limit: &RateLimit, record: &Record, now: Instant) -> (bool, Vec<Window>) {
+        let mut windows = self.windows.lock().unwrap();
+        let windows = &mut *windows;
+        let ended = windows.take_ended(limit, now);
+
+        let new_window = || Window {
+            started: now,
+            logged: 0,
+            suppressed: 0,
+            location: *record.location(),
+            level: record.level(),
+            tag: record.tag().to_string(),
+        };
+        let site = (record.module(), record.line(), record.column());
+        let window = windows.sites.entry(site).or_insert_with(new_window);
+        // windows with suppressed records are reported and removed above once they end
+        if now.duration_since(window.started) >= limit.interval {
+            *window = new_window();
+        }
+        if window.logged < limit.burst {
+            window.logged += 1;
+            (true, ended)
+        } else {
+            if window.suppressed == 0 {
+                let end = window.started + limit.interval;
+                if windows.next_report.is_none_or(|at| end < at) {
+                    windows.next_report = Some(end);
+                    self.changed.notify_one();
+                }
+            }
+            window.suppressed += 1;
+            (false, ended)
+        }
+    }
+
+    fn log_summaries(&self, windows: Vec<Window>) -> Result<(), D::Err> {
+        for window in windows {
+            let rs = RecordStatic {
+                location: &window.location,
+                tag: &window.tag,
+                level: window.level,
+            };
+            let msg = format_args!("suppressed {} similar messages", window.suppressed);
+            let kv = kv!("suppressed" => window.suppressed);
+            let summary = Record::new(&rs, &msg, BorrowedKV(&kv));
+            // suppressed records had their own context
+            self.drain.log(&summary, &self.values)?;
+        }
+        Ok(())
+    }
+}
+
+// logs summaries of sites that went quiet, until the drain is dropped
+fn report<D: Drain>(shared: &Shared<D>, limit: &RateLimit) {
+    let mut windows = shared.windows.lock().unwrap();
+    while !windows.closed {
+        let now = Instant::now();
+        windows = match windows.next_report {
+            Some(at) if at <= now => {
+                let ended = windows.take_ended(limit, now);
+                drop(windows);
+                let _ = shared.log_summaries(ended);
+                shared.windows.lock().unwrap()
+            }
+            Some(at) => shared.changed.wait_timeout(windows, at - now).unwrap().0,
+            None => shared.changed.wait(windows).unwrap(),
+        };
+    }
+}
+
+impl<D: Drain> Drain for RateLimitDrain<D> {
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        self.log_at(record, values, Instant::now())
+    }
+}
+
+impl<D: Drain> RateLimitDrain<D> {
+    fn log_at(
+        &self,
+        record: &Record,
+        values: &OwnedKVList,
+        now: Instant,
+    ) -> Result<Option<D::Ok>, D::Err> {
+        let shared = &*self.shared;
+        let limit = match &shared.limit {
+            Some(limit) => limit,
+            None => return shared.drain.log(record, values).map(Some),
+        };
+        let (admitted, ended) = shared.admit(limit, record, now);
+        shared.log_summaries(ended)?;
+        if admitted {
+            shared.drain.log(record, values).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<D: Drain> Drop for RateLimitDrain<D> {
+    fn drop(&mut self) {
+        let pending = match self.shared.windows.lock() {
+            Ok(mut windows) => {
+                windows.closed = true;
+                let mut pending = std::mem::take(&mut windows.sites)
+                    .into_values()
+                    .filter(|w| w.suppressed > 0)
+                    .collect::<Vec<_>>();
+                pending.sort_by_key(|w| w.started);
+                pending
+            }
+            Err(_) => vec![],
+        };
+        self.shared.changed.notify_one();
+        if let Some(reporter) = self.reporter.lock().unwrap().take() {
+            let _ = reporter.join();
+        }
+        let _ = self.shared.log_summaries(pending);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::{b, o, Level, Logger};
+
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    impl Drain for Messages {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &Record, _: &OwnedKVList) -> Result<(), slog::Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    const LIMIT: RateLimit = RateLimit {
+        burst: 2,
+        interval: Duration::from_secs(10),
+    };
+
+    #[test]
+    fn test_rate_limit() {
+        let messages = Arc::new(Mutex::new(vec![]));
+        let drain = RateLimitDrain::new(Messages(messages.clone()), Some(LIMIT), o!().into());
+        let logger = Logger::root(drain.fuse(), o!());
+        for i in 0..5 {
+            slog::info!(logger, "outage {}", i);
+            slog::info!(logger, "other");
+        }
+        assert_eq!(
+            *messages.lock().unwrap(),
+            vec!["outage 0", "other", "outage 1", "other"]
+        );
+    }
+
+    #[test]
+    fn test_suppressed_summary() {
+        let messages = Arc::new(Mutex::new(vec![]));
+        let drain = RateLimitDrain::new(Messages(messages.clone()), Some(LIMIT), o!().into());
+        let values = OwnedKVList::from(o!());
+        // records of the same call site
+        let log = |i: u32, now: Instant| {
+            let args = format_args!("outage {}", i);
+            let record = slog::record!(Level::Info, "", &args, b!());
+            drain.log_at(&record, &values, now).unwrap();
+        };
+        let now = Instant::now();
+        (0..5).for_each(|i| log(i, now));
+        log(5, now + LIMIT.interval);
+        assert_eq!(
+            *messages.lock().unwrap(),
+            vec![
+                "outage 0",
+                "outage 1",
+                "suppressed 3 similar messages",
+                "outage 5"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quiet_site_summary() {
+        let messages = Arc::new(Mutex::new(vec![]));
+        let drain = RateLimitDrain::new(Messages(messages.clone()), Some(LIMIT), o!().into());
+        let values = OwnedKVList::from(o!());
+        let now = Instant::now();
+        // a site floods and goes quiet
+        for i in 0..5 {
+            let args = format_args!("outage {}", i);
+            let record = slog::record!(Level::Info, "", &args, b!());
+            drain.log_at(&record, &values, now).unwrap();
+        }
+        // and another one logs after the interval
+        let args = format_args!("recovered");
+        let record = slog::record!(Level::Info, "", &args, b!());
+        drain
+            .log_at(&record, &values, now + LIMIT.interval)
+            .unwrap();
+        assert_eq!(
+            *messages.lock().unwrap(),
+            vec![
+                "outage 0",
+                "outage 1",
+                "suppressed 3 similar messages",
+                "recovered"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pending_summaries() {
+        // a site floods and goes quiet, its summary is logged once the interval ends
+        let messages = Arc::new(Mutex::new(vec![]));
+        let limit = RateLimit {
+            burst: 1,
+            interval: Duration::from_millis(50),
+        };
+        let drain = RateLimitDrain::new(Messages(messages.clone()), Some(limit), o!().into());
+        let logger = Logger::root(drain.fuse(), o!());
+        for _ in 0..3 {
+            slog::info!(logger, "outage");
+        }
+        let started = Instant::now();
+        while messages.lock().unwrap().len() < 2 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            *messages.lock().unwrap(),
+            vec!["outage", "suppressed 2 similar messages"]
+        );
+
+        // or when the drain is dropped
+        let messages = Arc::new(Mutex::new(vec![]));
+        let drain = RateLimitDrain::new(Messages(messages.clone()), Some(LIMIT), o!().into());
+        let logger = Logger::root(drain.fuse(), o!());
+        for _ in 0..5 {
+            slog::info!(logger, "outage");
+        }
+        drop(logger);
+        assert_eq!(
+            *messages.lock().unwrap(),
+            vec!["outage", "outage", "suppressed 3 similar messages"]
+        );
+    }
+}
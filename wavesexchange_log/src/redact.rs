@@ -0,0 +1,358 @@
+use slog::{BorrowedKV, Drain, Key, OwnedKV, OwnedKVList, Record, RecordStatic, Serializer, KV};
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
+pub(crate) const DEFAULT_KEYS: [&str; 5] =
+    ["authorization", "signature", "seed", "token", "password"];
+
+const MASK: &str = "***";
+
+/// Masks values of sensitive keys.
+///
+/// A key is sensitive if it ends with one of the configured names, comparing segments split
+/// by `_`, `.` and `-` and ignoring case, e.g. `access_token` or `X-Api-Token` for `token`,
+/// but not `tokens_count` or `token_len`. Values are masked in fields with such keys,
+/// and in messages and string fields, where they follow the key as in `token=..`, `"token": ".."`
+/// (URL query params, JSON) or `Authorization: Bearer ..` (headers).
+#[derive(Debug)]
+pub(crate) struct Redactor {
+    // segments of the names, lowercase
+    keys: Vec<Vec<String>>,
+}
+
+impl Redactor {
+    pub(crate) fn new(keys: &[String]) -> Self {
+        Redactor {
+            keys: keys
+                .iter()
+                .map(|k| {
+                    segments(k)
+                        .map(|s| s.to_ascii_lowercase())
+                        .collect::<Vec<_>>()
+                })
+                .filter(|k| !k.is_empty())
+                .collect(),
+        }
+    }
+
+    fn is_sensitive(&self, key: &str) -> bool {
+        let segments = segments(key).collect::<Vec<_>>();
+        self.keys.iter().any(|k| {
+            segments.len() >= k.len()
+                && segments[segments.len() - k.len()..]
+                    .iter()
+                    .zip(k)
+                    .all(|(s, k)| s.eq_ignore_ascii_case(k))
+        })
+    }
+
+    // whether the text can contain a sensitive key at all
+    fn mentions_key(&self, text: &str) -> bool {
+        self.keys
+            .iter()
+            .any(|k| k.iter().all(|s| contains_ignore_case(text, s)))
+    }
+
+    pub(crate) fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if !self.mentions_key(text) {
+            return Cow::Borrowed(text);
+        }
+        let bytes = text.as_bytes();
+        let len = bytes.len();
+        let skip_spaces = |mut i: usize| {
+            while i < len && bytes[i] == b' ' {
+                i += 1;
+            }
+            i
+        };
+        let mut redacted = String::with_capacity(len);
+        let mut copied = 0;
+        let mut i = 0;
+        while i < len {
+            if !is_ident(bytes[i]) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < len && is_ident(bytes[i]) {
+                i += 1;
+            }
+            if !self.is_sensitive(&text[start..i]) {
+                continue;
+            }
+            // closing quote of a JSON key, then the separator
+            let mut j = i;
+            if j < len && is_quote(bytes[j]) {
+                j += 1;
+            }
+            j = skip_spaces(j);
+            if j == len || (bytes[j] != b'=' && bytes[j] != b':') {
+                continue;
+            }
+            j = skip_spaces(j + 1);
+            let (value_start, value_end) = if j < len && is_quote(bytes[j]) {
+                let quote = bytes[j];
+                let value_start = j + 1;
+                j = value_start;
+                while j < len && bytes[j] != quote {
+                    // an escaped quote doesn't end the value
+                    j += if bytes[j] == b'\\' { 2 } else { 1 };
+                }
+                j = j.min(len);
+                (value_start, j)
+            } else {
+                let value_start = j;
+                while j < len && !is_delimiter(bytes[j]) {
+                    j += 1;
+                }
+                // credentials follow the scheme in an authorization header
+                let scheme = &text[value_start..j];
+                if (scheme.eq_ignore_ascii_case("bearer") || scheme.eq_ignore_ascii_case("basic"))
+                    && j < len
+                    && bytes[j] == b' '
+                {
+                    j += 1;
+                    while j < len && !is_delimiter(bytes[j]) {
+                        j += 1;
+                    }
+                }
+                (value_start, j)
+            };
+            if value_end > value_start {
+                redacted.push_str(&text[copied..value_start]);
+                redacted.push_str(MASK);
+                copied = value_end;
+            }
+            i = j;
+        }
+        if copied == 0 {
+            return Cow::Borrowed(text);
+        }
+        redacted.push_str(&text[copied..]);
+        Cow::Owned(redacted)
+    }
+}
+
+fn segments(key: &str) -> impl Iterator<Item = &str> {
+    key.split(['_', '.', '-']).filter(|s| !s.is_empty())
+}
+
+fn contains_ignore_case(haystack: &str, lowercase_needle: &str) -> bool {
+    haystack
+        .as_bytes()
+        .windows(lowercase_needle.len())
+        .any(|w| w.eq_ignore_ascii_case(lowercase_needle.as_bytes()))
+}
+
+fn is_ident(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'-' || b == b'.'
+}
+
+fn is_quote(b: u8) -> bool {
+    b == b'"' || b == b'\''
+}
+
+fn is_delimiter(b: u8) -> bool {
+    b.is_ascii_whitespace() || matches!(b, b'&' | b',' | b';' | b'"' | b'\'' | b')' | b'}' | b']')
+}
+
+/// Redacts the message, fields of the record and of its loggers before passing it on
+pub(crate) struct RedactDrain<D> {
+    drain: D,
+    redactor: Option<Arc<Redactor>>,
+}
+
+impl<D> RedactDrain<D> {
+    pub(crate) fn new(drain: D, keys: &[String]) -> Self {
+        RedactDrain {
+            drain,
+            redactor: (!keys.is_empty()).then(|| Arc::new(Redactor::new(keys))),
+        }
+    }
+}
+
+impl<D: Drain> Drain for RedactDrain<D> {
+    type Ok = D::Ok;
+    type Err = D::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        let redactor = match &self.redactor {
+            Some(redactor) => redactor,
+            None => return self.drain.log(record, values),
+        };
+        let msg = record.msg().to_string();
+        let msg = redactor.redact(&msg);
+        let args = format_args!("{}", msg);
+        let rs = RecordStatic {
+            location: record.location(),
+            tag: record.tag(),
+            level: record.level(),
+        };
+        let kv = Redacted {
+            kv: record.kv(),
+            redactor: redactor.clone(),
+        };
+        let record = Record::new(&rs, &args, BorrowedKV(&kv));
+        let values = OwnedKVList::from(OwnedKV(Redacted {
+            kv: values.clone(),
+            redactor: redactor.clone(),
+        }));
+        self.drain.log(&record, &values)
+    }
+}
+
+struct Redacted<T> {
+    kv: T,
+    redactor: Arc<Redactor>,
+}
+
+impl<T: KV> KV for Redacted<T> {
+    fn serialize(&self, record: &Record, ser: &mut dyn Serializer) -> slog::Result {
+        let mut ser = RedactSerializer {
+            ser,
+            redactor: &self.redactor,
+        };
+        self.kv.serialize(record, &mut ser)
+    }
+}
+
+struct RedactSerializer<'a> {
+    ser: &'a mut dyn Serializer,
+    redactor: &'a Redactor,
+}
+
+macro_rules! emit_unless_sensitive {
+    ($($method:ident: $t:ty),+) => {
+        $(
+            fn $method(&mut self, key: Key, val: $t) -> slog::Result {
+                if self.redactor.is_sensitive(key) {
+                    return self.ser.emit_str(key, MASK);
+                }
+                self.ser.$method(key, val)
+            }
+        )+
+    };
+}
+
+impl Serializer for RedactSerializer<'_> {
+    emit_unless_sensitive!(
+        emit_usize: usize, emit_isize: isize, emit_bool: bool, emit_char: char,
+        emit_u8: u8, emit_i8: i8, emit_u16: u16, emit_i16: i16, emit_u32: u32, emit_i32: i32,
+        emit_u64: u64, emit_i64: i64, emit_f32: f32, emit_f64: f64
+    );
+
+    fn emit_unit(&mut self, key: Key) -> slog::Result {
+        self.ser.emit_unit(key)
+    }
+
+    fn emit_none(&mut self, key: Key) -> slog::Result {
+        self.ser.emit_none(key)
+    }
+
+    fn emit_str(&mut self, key: Key, val: &str) -> slog::Result {
+        if self.redactor.is_sensitive(key) {
+            return self.ser.emit_str(key, MASK);
+        }
+        self.ser.emit_str(key, &self.redactor.redact(val))
+    }
+
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        if self.redactor.is_sensitive(key) {
+            return self.ser.emit_str(key, MASK);
+        }
+        let text = val.to_string();
+        match self.redactor.redact(&text) {
+            Cow::Borrowed(_) => self.ser.emit_arguments(key, val),
+            Cow::Owned(redacted) => self.ser.emit_str(key, &redacted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::{o, Logger};
+    use std::sync::Mutex;
+
+    fn redactor() -> Redactor {
+        let keys = DEFAULT_KEYS
+            .iter()
+            .map(|k| k.to_string())
+            .collect::<Vec<_>>();
+        Redactor::new(&keys)
+    }
+
+    #[test]
+    fn test_redact_text() {
+        let redactor = redactor();
+        let cases = [
+            (
+                "GET https://api/assets?ids=a&access_token=abc123&x=1",
+                "GET https://api/assets?ids=a&access_token=***&x=1",
+            ),
+            (
+                r#"body: {"seed": "word word word", "amount": 1}"#,
+                r#"body: {"seed": "***", "amount": 1}"#,
+            ),
+            (
+                "headers: Authorization: Bearer eyJhbGc.x.y, Accept: */*",
+                "headers: Authorization: ***, Accept: */*",
+            ),
+            ("password=", "password="),
+            ("tokens count 5", "tokens count 5"),
+            ("tokens_count=5&seed_len=3", "tokens_count=5&seed_len=3"),
+            (
+                r#"{"password": "a\"b", "token": "c\\", "amount": 1}"#,
+                r#"{"password": "***", "token": "***", "amount": 1}"#,
+            ),
+            ("no secrets here", "no secrets here"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(redactor.redact(text), expected);
+        }
+    }
+
+    struct Collect(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl Serializer for Collect {
+        fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+            self.0
+                .lock()
+                .unwrap()
+                .push((key.to_string(), val.to_string()));
+            Ok(())
+        }
+    }
+
+    impl Drain for Collect {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), slog::Never> {
+            let mut ser = Collect(self.0.clone());
+            ser.emit_str("msg", &record.msg().to_string()).unwrap();
+            record.kv().serialize(record, &mut ser).unwrap();
+            values.serialize(record, &mut ser).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_redact_drain() {
+        let fields = Arc::new(Mutex::new(vec![]));
+        let keys = vec!["token".to_string(), "seed".to_string()];
+        let drain = RedactDrain::new(Collect(fields.clone()), &keys);
+        let logger = Logger::root(drain.fuse(), o!("api_token" => "secret"));
+        slog::info!(logger, "requesting {}", "/?token=abc";
+            "seed" => 42, "url" => "/?a=1&token=abc", "tokens_count" => 2);
+
+        let fields = fields.lock().unwrap().clone();
+        let field = |key: &str| fields.iter().find(|(k, _)| k == key).unwrap().1.clone();
+        assert_eq!(field("msg"), "requesting /?token=***");
+        assert_eq!(field("seed"), "***");
+        assert_eq!(field("url"), "/?a=1&token=***");
+        assert_eq!(field("api_token"), "***");
+        assert_eq!(field("tokens_count"), "2");
+    }
+}
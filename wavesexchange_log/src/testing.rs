@@ -0,0 +1,214 @@
+//! Capturing log records in tests.
+//!
+//! ```
+//! # use wavesexchange_log::{warn, slog::Level, testing::capture};
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let ((), logs) = capture(async {
+//!     warn!("upstream is unavailable"; "status" => 503);
+//! })
+//! .await;
+//! assert_eq!(
+//!     logs.query()
+//!         .level(Level::Warning)
+//!         .message("unavailable")
+//!         .field("status", "503")
+//!         .count(),
+//!     1
+//! );
+//! # }
+//! ```
+//!
+//! Records of all levels logged by the task with the usual macros are captured,
+//! instead of being written to the global `LOGGER`. The level filter and other
+//! options of `LoggerConfig` aren't applied. Tasks spawned by the captured one aren't captured,
+//! so tests capturing logs can run in parallel.
+//!
+//! Enabled with the `testing` feature.
+
+use crate::context::{with_root, with_root_sync};
+use slog::{o, Drain, Key, Level, Logger, OwnedKVList, Record, Serializer, KV};
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+/// Log record with its message and fields formatted as strings
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapturedRecord {
+    pub level: Level,
+    pub module: &'static str,
+    pub msg: String,
+    /// Fields of the record, then of the log context
+    pub fields: Vec<(String, String)>,
+}
+
+impl CapturedRecord {
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Records captured by `capture`, clones share them
+#[derive(Clone, Debug, Default)]
+pub struct Captured {
+    records: Arc<Mutex<Vec<CapturedRecord>>>,
+}
+
+impl Captured {
+    pub fn records(&self) -> Vec<CapturedRecord> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// Query records, all of them match an empty query
+    pub fn query(&self) -> Query {
+        Query {
+            records: self.records(),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear()
+    }
+
+    fn logger(&self) -> Logger {
+        let drain = CaptureDrain {
+            records: self.records.clone(),
+        };
+        Logger::root(drain.fuse(), o!())
+    }
+}
+
+/// Records matching all the conditions
+#[derive(Clone, Debug)]
+pub struct Query {
+    records: Vec<CapturedRecord>,
+}
+
+impl Query {
+    pub fn level(self, level: Level) -> Self {
+        self.filter(|r| r.level == level)
+    }
+
+    /// Message contains `text`
+    pub fn message(self, text: &str) -> Self {
+        self.filter(|r| r.msg.contains(text))
+    }
+
+    /// Field `key` is formatted as `value`
+    pub fn field(self, key: &str, value: &str) -> Self {
+        self.filter(|r| r.field(key) == Some(value))
+    }
+
+    pub fn filter(mut self, f: impl Fn(&CapturedRecord) -> bool) -> Self {
+        self.records.retain(|r| f(r));
+        self
+    }
+
+    pub fn count(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn exists(&self) -> bool {
+        !self.records.is_empty()
+    }
+
+    pub fn records(self) -> Vec<CapturedRecord> {
+        self.records
+    }
+}
+
+/// Run `fut` capturing records it logs
+pub async fn capture<F: Future>(fut: F) -> (F::Output, Captured) {
+    let captured = Captured::default();
+    let output = with_root(captured.logger(), fut).await;
+    (output, captured)
+}
+
+/// Run `f` capturing records it logs
+pub fn capture_sync<R>(f: impl FnOnce() -> R) -> (R, Captured) {
+    let captured = Captured::default();
+    let output = with_root_sync(captured.logger(), f);
+    (output, captured)
+}
+
+struct CaptureDrain {
+    records: Arc<Mutex<Vec<CapturedRecord>>>,
+}
+
+impl Drain for CaptureDrain {
+    type Ok = ();
+    type Err = slog::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), slog::Error> {
+        let mut fields = Fields(vec![]);
+        record.kv().serialize(record, &mut fields)?;
+        values.serialize(record, &mut fields)?;
+        self.records.lock().unwrap().push(CapturedRecord {
+            level: record.level(),
+            module: record.module(),
+            msg: record.msg().to_string(),
+            fields: fields.0,
+        });
+        Ok(())
+    }
+}
+
+struct Fields(Vec<(String, String)>);
+
+impl Serializer for Fields {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        self.0.push((key.to_string(), val.to_string()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{debug, in_span, info, LogContext};
+
+    #[tokio::test]
+    async fn test_capture() {
+        let (value, logs) = capture(async {
+            debug!("loading {} assets", 2; "source" => "node");
+            LogContext::new()
+                .with("req_id", "abc")
+                .scope(in_span(async {
+                    info!("loaded");
+                    42
+                }))
+                .await
+        })
+        .await;
+        assert_eq!(value, 42);
+
+        let records = logs.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].level, Level::Debug);
+        assert_eq!(records[0].msg, "loading 2 assets");
+        assert_eq!(records[0].field("source"), Some("node"));
+        assert_eq!(records[1].field("req_id"), Some("abc"));
+        assert!(records[1].field("trace_id").is_some());
+
+        assert!(logs.query().level(Level::Info).message("load").exists());
+        assert!(!logs.query().level(Level::Warning).exists());
+        assert_eq!(logs.query().field("req_id", "abc").count(), 1);
+    }
+
+    #[test]
+    fn test_capture_sync() {
+        let ((), logs) = capture_sync(|| {
+            crate::timer!("sync", level = info; "id" => 1);
+        });
+        let records = logs
+            .query()
+            .field("timer", "sync")
+            .field("id", "1")
+            .records();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].field("elapsed_ms").is_some());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::context::{current_span, LogContext};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const SAMPLED: u8 = 0x01;
+
+/// Trace and span ids of the W3C Trace Context, compatible with OpenTelemetry.
+///
+/// Put into a `LogContext`, it adds `trace_id` and `span_id` to records,
+/// and is sent to other services in the `traceparent` header.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SpanContext {
+    trace_id: u128,
+    span_id: u64,
+    flags: u8,
+}
+
+impl SpanContext {
+    /// First span of a new trace
+    pub fn new_root() -> Self {
+        SpanContext {
+            trace_id: (u128::from(random_id()) << 64) | u128::from(random_id()),
+            span_id: random_id(),
+            flags: SAMPLED,
+        }
+    }
+
+    /// New span of the same trace
+    pub fn child(&self) -> Self {
+        SpanContext {
+            span_id: random_id(),
+            ..*self
+        }
+    }
+
+    /// Parse a `traceparent` header value, e.g. `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+    ///
+    /// The parsed span is the caller's one, handle the request in its `child`.
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+        // lowercase only, as the spec requires
+        let is_hex = |s: &str, len: usize| {
+            s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        // later versions may append fields, version 00 must not have them
+        if !is_hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+        if !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+            return None;
+        }
+        let span = SpanContext {
+            trace_id: u128::from_str_radix(trace_id, 16).ok()?,
+            span_id: u64::from_str_radix(span_id, 16).ok()?,
+            flags: u8::from_str_radix(flags, 16).ok()?,
+        };
+        // all-zero ids are invalid
+        if span.trace_id == 0 || span.span_id == 0 {
+            return None;
+        }
+        Some(span)
+    }
+
+    pub fn to_traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            self.trace_id(),
+            self.span_id(),
+            self.flags
+        )
+    }
+
+    pub fn trace_id(&self) -> String {
+        format!("{:032x}", self.trace_id)
+    }
+
+    pub fn span_id(&self) -> String {
+        format!("{:016x}", self.span_id)
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.flags & SAMPLED != 0
+    }
+}
+
+impl fmt::Debug for SpanContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SpanContext({})", self.to_traceparent())
+    }
+}
+
+/// Run `fut` in a child span of the current one, or in a new trace if there is no current span
+pub async fn in_span<F: Future>(fut: F) -> F::Output {
+    let span = match current_span() {
+        Some(span) => span.child(),
+        None => SpanContext::new_root(),
+    };
+    LogContext::new().span(span).scope(fut).await
+}
+
+// non-zero random id, ids don't need a cryptographically secure generator
+fn random_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        let id = hasher.finish();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traceparent() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let span = SpanContext::from_traceparent(header).unwrap();
+        assert_eq!(span.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span.span_id(), "00f067aa0ba902b7");
+        assert!(span.is_sampled());
+        assert_eq!(span.to_traceparent(), header);
+
+        let child = span.child();
+        assert_eq!(child.trace_id(), span.trace_id());
+        assert_ne!(child.span_id(), span.span_id());
+
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473x-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00F067AA0BA902B7-01",
+        ] {
+            assert_eq!(SpanContext::from_traceparent(invalid), None, "{}", invalid);
+        }
+        assert!(SpanContext::from_traceparent(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra"
+        )
+        .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_in_span() {
+        assert_eq!(current_span(), None);
+        let (outer, inner) = in_span(async {
+            let outer = current_span().unwrap();
+            let inner = in_span(async { current_span().unwrap() }).await;
+            (outer, inner)
+        })
+        .await;
+        assert_eq!(outer.trace_id(), inner.trace_id());
+        assert_ne!(outer.span_id(), inner.span_id());
+    }
+}
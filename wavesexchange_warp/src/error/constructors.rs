@@ -12,6 +12,7 @@ mod offsets {
     pub const METHOD_NOT_ALLOWED: u32 = 7;
     pub const UNSUPPORTED_MEDIA_TYPE: u32 = 8;
     pub const LIMITS: u32 = 9;
+    pub const SERVICE_UNAVAILABLE: u32 = 10;
 }
 
 pub fn authentication(code_prefix: u16) -> Response {
@@ -196,3 +197,36 @@ pub fn timeout(code_prefix: u16) -> Response {
         None,
     )
 }
+
+pub fn service_unavailable(code_prefix: u16) -> Response {
+    Response::singleton(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Service unavailable.",
+        code_prefix as u32 * 10000 + offsets::SERVICE_UNAVAILABLE * 100,
+        None,
+    )
+}
+
+/// Response for a failed loader, errors of `load_fn` itself are converted with `other`
+#[cfg(feature = "loaders")]
+pub fn loader<K: std::fmt::Debug, E: std::fmt::Debug>(
+    code_prefix: u16,
+    err: &wavesexchange_loaders::LoaderError<K, E>,
+    other: impl FnOnce(&E) -> Response,
+) -> Response {
+    use crate::error::response::ErrorDetails;
+    use wavesexchange_loaders::LoaderError;
+
+    match err {
+        LoaderError::KeysMissing(keys) => Response::singleton(
+            StatusCode::NOT_FOUND,
+            "Not found.",
+            code_prefix as u32 * 10000 + offsets::NOT_FOUND * 100,
+            Some(ErrorDetails::single_item("keys", format!("{:?}", keys))),
+        ),
+        LoaderError::Timeout(_) => timeout(code_prefix),
+        LoaderError::CircuitOpen => service_unavailable(code_prefix),
+        LoaderError::Other(e) => other(e),
+        LoaderError::LengthMismatch { .. } | LoaderError::Internal(_) => internal(code_prefix),
+    }
+}
@@ -1,3 +1,5 @@
 pub mod error;
+#[cfg(feature = "loaders")]
+pub mod loaders;
 pub mod log;
 pub mod pagination;
@@ -0,0 +1,85 @@
+use std::convert::Infallible;
+use warp::Filter;
+use wavesexchange_loaders::LoaderContext;
+
+/// Creates a new `LoaderContext` for every request,
+/// so loaders scoped in it cache values only while the request is handled.
+///
+/// ```ignore
+/// warp::path!("assets")
+///     .and(loader_context())
+///     .and_then(|ctx: LoaderContext| async move { ... })
+/// ```
+pub fn loader_context() -> impl Filter<Extract = (LoaderContext,), Error = Infallible> + Clone {
+    warp::any().map(LoaderContext::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error;
+    use std::time::Duration;
+    use warp::http::StatusCode;
+    use wavesexchange_loaders::{Loader, LoaderError, NonCachedLoader};
+
+    #[derive(Clone)]
+    struct Echo;
+
+    #[wavesexchange_loaders::async_trait]
+    impl NonCachedLoader<u8, u8> for Echo {
+        type Error = ();
+
+        async fn load_fn(&mut self, keys: &[u8]) -> Result<Vec<u8>, ()> {
+            Ok(keys.to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loader_context() {
+        let filter = loader_context().and_then(|ctx: LoaderContext| async move {
+            let value = ctx.scoped(Echo).load(7).await.unwrap();
+            Ok::<_, Infallible>(value.to_string())
+        });
+        let res = warp::test::request().reply(&filter).await;
+        assert_eq!(res.body(), "7");
+    }
+
+    #[test]
+    fn test_loader_error_response() {
+        let not_found = error::loader(
+            1,
+            &LoaderError::<u8, ()>::KeysMissing(vec![1, 2]),
+            |_| unreachable!(),
+        );
+        assert_eq!(not_found.status, StatusCode::NOT_FOUND);
+        assert_eq!(not_found.errors[0].code, 10400);
+
+        let timeout = error::loader(
+            1,
+            &LoaderError::<u8, ()>::Timeout(Duration::from_secs(1)),
+            |_| unreachable!(),
+        );
+        assert_eq!(timeout.status, StatusCode::GATEWAY_TIMEOUT);
+
+        let circuit_open =
+            error::loader(1, &LoaderError::<u8, ()>::CircuitOpen, |_| unreachable!());
+        assert_eq!(circuit_open.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(circuit_open.errors[0].code, 11000);
+
+        let internal = error::loader(
+            1,
+            &LoaderError::<u8, ()>::LengthMismatch {
+                expected: 2,
+                got: 1,
+            },
+            |_| unreachable!(),
+        );
+        assert_eq!(internal.status, StatusCode::INTERNAL_SERVER_ERROR);
+
+        let other = error::loader(1, &LoaderError::<u8, _>::Other(404), |code| {
+            assert_eq!(*code, 404);
+            error::not_found(1)
+        });
+        assert_eq!(other.status, StatusCode::NOT_FOUND);
+    }
+}
@@ -1,4 +1,14 @@
-use wavesexchange_log::info;
+use crate::error;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use warp::http::{HeaderMap, Request};
+use warp::hyper::{body::Bytes, service::Service, Body};
+use warp::{reply::Response, Filter, Rejection, Reply};
+use wavesexchange_log::{current_filter, info, set_filter};
+pub use wavesexchange_log::{LogContext, SpanContext};
 
 pub fn access(info: warp::log::Info) {
     let headers = info.request_headers();
@@ -22,3 +32,177 @@ pub fn access(info: warp::log::Info) {
         "protocol" => format!("{:?}", info.version())
     );
 }
+
+/// Creates a `LogContext` for every request, with `req_id` from the `x-request-id` header if it's set,
+/// and a span continuing the trace of the W3C `traceparent` header, or starting a new trace without it.
+///
+/// Handlers scoped in it log every record with the request id, `trace_id` and `span_id`:
+///
+/// ```ignore
+/// warp::path!("assets")
+///     .and(log::context())
+///     .and_then(|ctx: LogContext| ctx.scope(async move { ... }))
+/// ```
+pub fn context() -> impl Filter<Extract = (LogContext,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: HeaderMap| request_context(&headers))
+}
+
+fn request_context(headers: &HeaderMap) -> LogContext {
+    let header = |name| headers.get(name).and_then(|h| h.to_str().ok());
+    let span = match header("traceparent").and_then(SpanContext::from_traceparent) {
+        Some(parent) => parent.child(),
+        None => SpanContext::new_root(),
+    };
+    let ctx = LogContext::new().span(span);
+    match header("x-request-id") {
+        Some(req_id) => ctx.with("req_id", req_id),
+        None => ctx,
+    }
+}
+
+/// Serves `filter`, handling every request in the `LogContext` created by `context()`,
+/// so that plain handlers log records with the request id and span without scoping them.
+///
+/// Filters can't run other filters in a scope, so it's a hyper `Service`:
+///
+/// ```ignore
+/// let svc = log::with_log_context(routes);
+/// let make_svc = hyper::service::make_service_fn(move |_| {
+///     let svc = svc.clone();
+///     async move { Ok::<_, Infallible>(svc) }
+/// });
+/// hyper::Server::bind(&addr).serve(make_svc).await?;
+/// ```
+pub fn with_log_context<F>(filter: F) -> LogContextService<F> {
+    LogContextService { filter }
+}
+
+/// Service returned by `with_log_context`
+#[derive(Clone, Debug)]
+pub struct LogContextService<F> {
+    filter: F,
+}
+
+impl<F, R> Service<Request<Body>> for LogContextService<F>
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + 'static,
+    R: Reply,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let ctx = request_context(req.headers());
+        let mut svc = warp::service(self.filter.clone());
+        // the filter future is created in the scope too, some filters run right away
+        Box::pin(ctx.scope(async move { svc.call(req).await }))
+    }
+}
+
+/// `GET /log/level` returns the level filter of the global logger,
+/// `PUT /log/level` replaces it with the filter in the request body, e.g. `info,my_service::db=trace`.
+///
+/// Both respond with the current filter as plain text. The route isn't protected,
+/// so it should be served on an internal port only.
+pub fn level(code_prefix: u16) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let get = warp::get().map(|| current_filter().into_response());
+    let put = warp::put()
+        .and(warp::body::content_length_limit(1024))
+        .and(warp::body::bytes())
+        .map(move |body: Bytes| {
+            let filter = String::from_utf8_lossy(&body);
+            match set_filter(filter.trim()) {
+                Ok(()) => {
+                    info!("log level filter changed"; "filter" => current_filter());
+                    current_filter().into_response()
+                }
+                Err(e) => {
+                    let details = HashMap::from([("filter".to_string(), e.to_string())]);
+                    error::validation::invalid_parameter(code_prefix, Some(details)).into_response()
+                }
+            }
+        });
+    warp::path!("log" / "level").and(get.or(put).unify())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::http::StatusCode;
+    use wavesexchange_log::testing::capture;
+
+    #[tokio::test]
+    async fn test_context() {
+        let ctx = warp::test::request()
+            .header("x-request-id", "abc")
+            .filter(&context())
+            .await
+            .unwrap();
+        assert_eq!(ctx.get("req_id"), Some("abc"));
+
+        let ctx = warp::test::request().filter(&context()).await.unwrap();
+        assert_eq!(ctx.get("req_id"), None);
+        assert!(ctx.get_span().is_some());
+
+        let parent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let ctx = warp::test::request()
+            .header("traceparent", parent)
+            .filter(&context())
+            .await
+            .unwrap();
+        let span = ctx.get_span().unwrap();
+        assert_eq!(span.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_ne!(span.span_id(), "00f067aa0ba902b7");
+    }
+
+    #[tokio::test]
+    async fn test_with_log_context() {
+        let route = warp::path!("assets").map(|| {
+            info!("loading assets");
+            "[]"
+        });
+        let mut svc = with_log_context(route);
+        let req = Request::get("/assets")
+            .header("x-request-id", "abc")
+            .body(Body::empty())
+            .unwrap();
+        let (res, logs) = capture(svc.call(req)).await;
+        assert_eq!(res.unwrap().status(), StatusCode::OK);
+        let records = logs.query().message("loading assets").records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].field("req_id"), Some("abc"));
+        assert!(records[0].field("trace_id").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_level() {
+        let res = warp::test::request()
+            .method("PUT")
+            .path("/log/level")
+            .body("info, my_service::db=trace")
+            .reply(&level(1))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body(), "info,my_service::db=trace");
+
+        let res = warp::test::request()
+            .path("/log/level")
+            .reply(&level(1))
+            .await;
+        assert_eq!(res.body(), "info,my_service::db=trace");
+
+        let res = warp::test::request()
+            .method("PUT")
+            .path("/log/level")
+            .body("my_service=loud")
+            .reply(&level(1))
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(current_filter(), "info,my_service::db=trace");
+    }
+}